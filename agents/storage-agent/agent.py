@@ -4,6 +4,9 @@ import hashlib
 import requests
 import asyncio
 import os
+import struct
+import base58
+from nacl.signing import SigningKey
 
 def get_file_from_directory(env, directory=".", extension=".mp3"):
     """Fallback: Verify the first .mp3 file in the registry."""
@@ -23,6 +26,26 @@ def get_file_from_directory(env, directory=".", extension=".mp3"):
         env.add_system_log(f"get_file_from_directory: error - {str(e)}")
         return None, None
 
+def borsh_string(value):
+    """Borsh encoding of a string: u32 little-endian length, then UTF-8 bytes."""
+    data = value.encode("utf-8")
+    return struct.pack("<I", len(data)) + data
+
+def sign_upload_attestation(private_key, group_id, user_id, file_hash, ipfs_hash, size_bytes):
+    """Sign the (group_id, user_id, file_hash, ipfs_hash, size_bytes) claim checked by record_transaction."""
+    key_bytes = base58.b58decode(private_key.split(":", 1)[-1])
+    signing_key = SigningKey(key_bytes[:32])
+    claim = (
+        borsh_string(group_id)
+        + borsh_string(user_id)
+        + borsh_string(file_hash)
+        + borsh_string(ipfs_hash)
+        + struct.pack("<Q", size_bytes)
+    )
+    signature = signing_key.sign(claim).signature
+    public_key = "ed25519:" + base58.b58encode(bytes(signing_key.verify_key)).decode()
+    return {"public_key": public_key, "signature": signature.hex()}
+
 def upload_to_ipfs(file_data, filename, env):
     """Upload file to IPFS via Pinata."""
     url = "https://api.pinata.cloud/pinning/pinFileToIPFS"
@@ -136,11 +159,22 @@ def run(env: Environment):
 
     # Record transaction on NEAR
     group_id = env.env_vars.get("GROUP_ID", "theosis")
+    attestation_key = env.env_vars.get("ATTESTATION_PRIVATE_KEY")
+    if not attestation_key:
+        env.add_reply("Missing ATTESTATION_PRIVATE_KEY")
+        env.request_user_input()
+        return
     args = {
         "group_id": group_id,
         "user_id": user_id,
         "file_hash": file_hash,
-        "ipfs_hash": ipfs_hash
+        "ipfs_hash": ipfs_hash,
+        "size_bytes": str(len(file_data)),
+        "file_name": filename,
+        "mime_type": "audio/mpeg",
+        "attestation": sign_upload_attestation(
+            attestation_key, group_id, user_id, file_hash, ipfs_hash, len(file_data)
+        )
     }
     try:
         result = near.call(
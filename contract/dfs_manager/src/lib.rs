@@ -1,12 +1,37 @@
 // A smart contract to work with the DFS manager https://github.com/jcarbonnell/DFS_manager
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{near_bindgen, env, log, PanicOnDefault, AccountId, PromiseResult, Gas};
-use near_sdk::store::{IterableMap, LookupMap};
+use near_sdk::{near_bindgen, env, log, PanicOnDefault, AccountId, NearToken, Promise, PromiseOrValue, PromiseResult, Gas, PublicKey, CurveType};
+use near_sdk::store::{IterableMap, IterableSet, LookupMap, LookupSet};
 use near_sdk::serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
 use near_sdk::serde_json;
-use near_sdk::json_types::U128;
-use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_sdk::json_types::{U128, U64};
+use near_contract_standards::non_fungible_token::{NonFungibleToken, NonFungibleTokenEnumeration, NonFungibleTokenResolver, Token, TokenId};
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenCore;
+use near_contract_standards::non_fungible_token::events::{NftBurn, NftMint};
+use near_contract_standards::non_fungible_token::metadata::{NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC};
+use std::collections::HashMap;
+
+const MAX_POLICY_CHECKS: usize = 4; // Cross-contract calls per admission
+const MAX_BPS: u16 = 10_000;
+const MAX_REQUEST_MESSAGE_LEN: usize = 280; // Characters in a membership request message
+const DEFAULT_NFT_CONTRACT: &str = "1000fans.testnet";
+const DEFAULT_REPLICATION_TARGET: u32 = 1;
+const CHALLENGE_CHUNKS: u32 = 3; // Chunks sampled per retrievability challenge
+const CHALLENGE_WINDOW_NS: u64 = 3_600_000_000_000; // 1 hour to answer a challenge
+const ROTATION_TIMEOUT_NS: u64 = 7 * 24 * 3_600_000_000_000; // 7 days to re-encrypt a group
+const MAX_GROUP_DEPTH: usize = 8; // Levels in a group hierarchy, bounds inherited access checks
+const PROPOSAL_TTL_NS: u64 = 7 * 24 * 3_600_000_000_000; // 7 days to approve a council proposal
+const DEFAULT_UPGRADE_DELAY_NS: u64 = 2 * 24 * 3_600_000_000_000; // 2 days between staging and deploying code
+const MIN_UPGRADE_DELAY_NS: u64 = 24 * 3_600_000_000_000; // Members always get a day of notice
+const MEMBERSHIP_TOKEN_NAME: &str = "DFS Membership";
+const MEMBERSHIP_TOKEN_SYMBOL: &str = "DFSM";
+const INVITATION_TAG: &str = "invitation"; // Type tags of messages signed with a group signing key
+const SHARE_TAG: &str = "share";
+const EVENT_STANDARD: &str = "dfs_manager"; // NEP-297 event log
+const EVENT_VERSION: &str = "1.0.0";
+const STATE_VERSION_KEY: &[u8] = b"_version"; // Layout version of the stored state, absent for v0.2.0
+const STATE_VERSION: u32 = 1;
 
 #[near_bindgen]
 #[derive(PanicOnDefault)]
@@ -16,6 +41,65 @@ pub struct Contract {
     groups: LookupMap<String, Group>,
     group_members: LookupMap<String, Vec<AccountId>>,
     file_metadata: LookupMap<String, String>, // Stores file metadata by trans_id
+    group_signing_keys: LookupMap<String, PublicKey>, // Keys allowed to sign invitations, by group_id
+    redeemed_invitations: LookupSet<(String, u64)>, // (group_id, nonce) of used invitations
+    group_managers: LookupMap<String, Vec<AccountId>>,
+    membership_requests: LookupMap<(String, AccountId), MembershipRequest>,
+    pending_requests: LookupMap<String, Vec<AccountId>>, // Accounts with a pending request, by group_id
+    ft_gates: LookupMap<String, FtGate>, // Fungible token admission rule, by group_id
+    group_policies: LookupMap<String, AccessPolicy>, // Composite admission rule, by group_id
+    account_first_seen: LookupMap<AccountId, u64>, // First interaction timestamp, for account age checks
+    membership_prices: LookupMap<String, u128>, // Price in yoctoNEAR, by group_id
+    proceeds: LookupMap<AccountId, u128>, // Sales proceeds awaiting withdrawal
+    protocol_fee_bps: u16, // Contract owner's cut of sales, in basis points
+    file_prices: LookupMap<String, u128>, // Price in yoctoNEAR, by trans_id
+    file_entitlements: LookupSet<(String, AccountId)>, // (trans_id, buyer) of purchased files
+    file_sale_splits: LookupMap<String, u16>, // Uploader's share of file sales in basis points, by group_id
+    storage_quotas: LookupMap<String, StorageQuota>,
+    group_usage: LookupMap<String, StorageUsage>,
+    member_usage: LookupMap<(String, AccountId), StorageUsage>,
+    attestation_keys: IterableMap<PublicKey, AccountId>, // Storage agent signing keys and their agent
+    pinning_providers: IterableMap<AccountId, PinningProvider>,
+    file_pins: LookupMap<String, Vec<AccountId>>, // Providers pinning each file, by trans_id
+    replication_targets: LookupMap<String, u32>, // Minimum pinning providers per file, by group_id
+    chunk_roots: LookupMap<String, ChunkRoot>, // Chunk Merkle root of each file, by trans_id
+    challenges: LookupMap<u64, Challenge>,
+    next_challenge_id: u64,
+    key_epochs: LookupMap<String, u64>, // Incremented each time a group key is set
+    key_access_log: LookupMap<(String, u64), KeyAccess>, // Keyed by (group_id, sequence)
+    key_access_counts: LookupMap<String, u64>,
+    rotation_jobs: LookupMap<String, RotationJob>, // Latest key rotation of each group
+    rotated_files: LookupMap<(String, String), u64>, // Epoch of the latest rotation each file was re-encrypted under, by (group_id, trans_id)
+    rotation_previous_cids: LookupMap<(String, String), (u64, String)>, // CIDs replaced by a rotation, tagged with its new epoch
+    rotation_previous_keys: LookupMap<(String, String), (u64, Option<WrappedKey>)>, // Wrapped keys replaced by a rotation, tagged with its new epoch
+    rotation_changed_files: LookupMap<(String, u64), String>, // Files changed by the open rotation, by (group_id, index)
+    share_grants: LookupMap<(String, AccountId), ShareGrant>, // Redeemed share capabilities, by (trans_id, grantee)
+    revoked_shares: LookupSet<(String, u64)>, // (group_id, nonce) of revoked share capabilities
+    delegations: LookupMap<(String, AccountId), Delegation>, // Keyed by (group_id, delegate)
+    member_delegates: LookupMap<(String, AccountId), Vec<AccountId>>, // Delegates of each (group_id, delegator)
+    group_parents: LookupMap<String, String>,
+    group_children: LookupMap<String, Vec<String>>,
+    inheritance_opt_outs: LookupSet<String>, // Sub-groups not granting access to parent members
+    file_links: LookupMap<String, Vec<String>>, // Groups a file is linked into, by trans_id
+    pause_flags: IterableSet<(Option<String>, Option<PauseScope>)>, // (group_id, scope), None for all
+    council: Option<Council>, // When set, admin actions need council approval instead of the owner
+    proposals: LookupMap<u64, Proposal>,
+    next_proposal_id: u64,
+    staged_upgrade: Option<StagedUpgrade>,
+    upgrade_delay_ns: u64,
+    pending_upgrade_delay: Option<(u64, u64)>, // (delay_ns, effective_at) of a lowered delay
+    nft_contract: AccountId, // NEP-171 contract checked by add_group_member
+    group_linked_files: LookupMap<String, Vec<String>>, // trans_ids linked into each group
+    pending_file_links: LookupSet<(String, String)>, // (trans_id, group_id) awaiting the origin owner
+    membership_tokens: NonFungibleToken, // Built-in NEP-171 tokens, each granting membership of one group
+    token_groups: LookupMap<TokenId, String>, // Group granted by each membership token
+    member_token_counts: LookupMap<(String, AccountId), u32>, // Membership tokens held, by (group_id, owner)
+    soulbound_groups: LookupSet<String>, // Groups whose membership tokens cannot be transferred
+    token_only_members: LookupSet<(String, AccountId)>, // Members only through the membership tokens they hold
+    purchase_keys: LookupMap<(String, AccountId), String>, // Data keys wrapped for each buyer, by (trans_id, buyer)
+    link_keys: LookupMap<(String, String), (String, WrappedKey)>, // Data key of a linked file wrapped for the target group, with the CID it decrypts, by (trans_id, group_id)
+    fee_recipient: AccountId, // Receives the protocol fee of sales
+    legacy_transactions: Option<IterableMap<String, TransactionV0>>, // v0.2.0 records not yet re-encoded by migrate_transactions
     #[cfg(test)]
     mock_promise_result: Option<Vec<Token>>, // Test-only field to mock promise result
 }
@@ -28,6 +112,65 @@ impl BorshSerialize for Contract {
         BorshSerialize::serialize(&self.groups, writer)?;
         BorshSerialize::serialize(&self.group_members, writer)?;
         BorshSerialize::serialize(&self.file_metadata, writer)?;
+        BorshSerialize::serialize(&self.group_signing_keys, writer)?;
+        BorshSerialize::serialize(&self.redeemed_invitations, writer)?;
+        BorshSerialize::serialize(&self.group_managers, writer)?;
+        BorshSerialize::serialize(&self.membership_requests, writer)?;
+        BorshSerialize::serialize(&self.pending_requests, writer)?;
+        BorshSerialize::serialize(&self.ft_gates, writer)?;
+        BorshSerialize::serialize(&self.group_policies, writer)?;
+        BorshSerialize::serialize(&self.account_first_seen, writer)?;
+        BorshSerialize::serialize(&self.membership_prices, writer)?;
+        BorshSerialize::serialize(&self.proceeds, writer)?;
+        BorshSerialize::serialize(&self.protocol_fee_bps, writer)?;
+        BorshSerialize::serialize(&self.file_prices, writer)?;
+        BorshSerialize::serialize(&self.file_entitlements, writer)?;
+        BorshSerialize::serialize(&self.file_sale_splits, writer)?;
+        BorshSerialize::serialize(&self.storage_quotas, writer)?;
+        BorshSerialize::serialize(&self.group_usage, writer)?;
+        BorshSerialize::serialize(&self.member_usage, writer)?;
+        BorshSerialize::serialize(&self.attestation_keys, writer)?;
+        BorshSerialize::serialize(&self.pinning_providers, writer)?;
+        BorshSerialize::serialize(&self.file_pins, writer)?;
+        BorshSerialize::serialize(&self.replication_targets, writer)?;
+        BorshSerialize::serialize(&self.chunk_roots, writer)?;
+        BorshSerialize::serialize(&self.challenges, writer)?;
+        BorshSerialize::serialize(&self.next_challenge_id, writer)?;
+        BorshSerialize::serialize(&self.key_epochs, writer)?;
+        BorshSerialize::serialize(&self.key_access_log, writer)?;
+        BorshSerialize::serialize(&self.key_access_counts, writer)?;
+        BorshSerialize::serialize(&self.rotation_jobs, writer)?;
+        BorshSerialize::serialize(&self.rotated_files, writer)?;
+        BorshSerialize::serialize(&self.rotation_previous_cids, writer)?;
+        BorshSerialize::serialize(&self.rotation_previous_keys, writer)?;
+        BorshSerialize::serialize(&self.rotation_changed_files, writer)?;
+        BorshSerialize::serialize(&self.share_grants, writer)?;
+        BorshSerialize::serialize(&self.revoked_shares, writer)?;
+        BorshSerialize::serialize(&self.delegations, writer)?;
+        BorshSerialize::serialize(&self.member_delegates, writer)?;
+        BorshSerialize::serialize(&self.group_parents, writer)?;
+        BorshSerialize::serialize(&self.group_children, writer)?;
+        BorshSerialize::serialize(&self.inheritance_opt_outs, writer)?;
+        BorshSerialize::serialize(&self.file_links, writer)?;
+        BorshSerialize::serialize(&self.pause_flags, writer)?;
+        BorshSerialize::serialize(&self.council, writer)?;
+        BorshSerialize::serialize(&self.proposals, writer)?;
+        BorshSerialize::serialize(&self.next_proposal_id, writer)?;
+        BorshSerialize::serialize(&self.staged_upgrade, writer)?;
+        BorshSerialize::serialize(&self.upgrade_delay_ns, writer)?;
+        BorshSerialize::serialize(&self.pending_upgrade_delay, writer)?;
+        BorshSerialize::serialize(&self.nft_contract, writer)?;
+        BorshSerialize::serialize(&self.group_linked_files, writer)?;
+        BorshSerialize::serialize(&self.pending_file_links, writer)?;
+        BorshSerialize::serialize(&self.membership_tokens, writer)?;
+        BorshSerialize::serialize(&self.token_groups, writer)?;
+        BorshSerialize::serialize(&self.member_token_counts, writer)?;
+        BorshSerialize::serialize(&self.soulbound_groups, writer)?;
+        BorshSerialize::serialize(&self.token_only_members, writer)?;
+        BorshSerialize::serialize(&self.purchase_keys, writer)?;
+        BorshSerialize::serialize(&self.link_keys, writer)?;
+        BorshSerialize::serialize(&self.fee_recipient, writer)?;
+        BorshSerialize::serialize(&self.legacy_transactions, writer)?;
         Ok(())
     }
 }
@@ -39,12 +182,130 @@ impl BorshDeserialize for Contract {
         let groups = BorshDeserialize::deserialize(buf)?;
         let group_members = BorshDeserialize::deserialize(buf)?;
         let file_metadata = BorshDeserialize::deserialize(buf)?;
+        let group_signing_keys = BorshDeserialize::deserialize(buf)?;
+        let redeemed_invitations = BorshDeserialize::deserialize(buf)?;
+        let group_managers = BorshDeserialize::deserialize(buf)?;
+        let membership_requests = BorshDeserialize::deserialize(buf)?;
+        let pending_requests = BorshDeserialize::deserialize(buf)?;
+        let ft_gates = BorshDeserialize::deserialize(buf)?;
+        let group_policies = BorshDeserialize::deserialize(buf)?;
+        let account_first_seen = BorshDeserialize::deserialize(buf)?;
+        let membership_prices = BorshDeserialize::deserialize(buf)?;
+        let proceeds = BorshDeserialize::deserialize(buf)?;
+        let protocol_fee_bps = BorshDeserialize::deserialize(buf)?;
+        let file_prices = BorshDeserialize::deserialize(buf)?;
+        let file_entitlements = BorshDeserialize::deserialize(buf)?;
+        let file_sale_splits = BorshDeserialize::deserialize(buf)?;
+        let storage_quotas = BorshDeserialize::deserialize(buf)?;
+        let group_usage = BorshDeserialize::deserialize(buf)?;
+        let member_usage = BorshDeserialize::deserialize(buf)?;
+        let attestation_keys = BorshDeserialize::deserialize(buf)?;
+        let pinning_providers = BorshDeserialize::deserialize(buf)?;
+        let file_pins = BorshDeserialize::deserialize(buf)?;
+        let replication_targets = BorshDeserialize::deserialize(buf)?;
+        let chunk_roots = BorshDeserialize::deserialize(buf)?;
+        let challenges = BorshDeserialize::deserialize(buf)?;
+        let next_challenge_id = BorshDeserialize::deserialize(buf)?;
+        let key_epochs = BorshDeserialize::deserialize(buf)?;
+        let key_access_log = BorshDeserialize::deserialize(buf)?;
+        let key_access_counts = BorshDeserialize::deserialize(buf)?;
+        let rotation_jobs = BorshDeserialize::deserialize(buf)?;
+        let rotated_files = BorshDeserialize::deserialize(buf)?;
+        let rotation_previous_cids = BorshDeserialize::deserialize(buf)?;
+        let rotation_previous_keys = BorshDeserialize::deserialize(buf)?;
+        let rotation_changed_files = BorshDeserialize::deserialize(buf)?;
+        let share_grants = BorshDeserialize::deserialize(buf)?;
+        let revoked_shares = BorshDeserialize::deserialize(buf)?;
+        let delegations = BorshDeserialize::deserialize(buf)?;
+        let member_delegates = BorshDeserialize::deserialize(buf)?;
+        let group_parents = BorshDeserialize::deserialize(buf)?;
+        let group_children = BorshDeserialize::deserialize(buf)?;
+        let inheritance_opt_outs = BorshDeserialize::deserialize(buf)?;
+        let file_links = BorshDeserialize::deserialize(buf)?;
+        let pause_flags = BorshDeserialize::deserialize(buf)?;
+        let council = BorshDeserialize::deserialize(buf)?;
+        let proposals = BorshDeserialize::deserialize(buf)?;
+        let next_proposal_id = BorshDeserialize::deserialize(buf)?;
+        let staged_upgrade = BorshDeserialize::deserialize(buf)?;
+        let upgrade_delay_ns = BorshDeserialize::deserialize(buf)?;
+        let pending_upgrade_delay = BorshDeserialize::deserialize(buf)?;
+        let nft_contract = BorshDeserialize::deserialize(buf)?;
+        let group_linked_files = BorshDeserialize::deserialize(buf)?;
+        let pending_file_links = BorshDeserialize::deserialize(buf)?;
+        let membership_tokens = BorshDeserialize::deserialize(buf)?;
+        let token_groups = BorshDeserialize::deserialize(buf)?;
+        let member_token_counts = BorshDeserialize::deserialize(buf)?;
+        let soulbound_groups = BorshDeserialize::deserialize(buf)?;
+        let token_only_members = BorshDeserialize::deserialize(buf)?;
+        let purchase_keys = BorshDeserialize::deserialize(buf)?;
+        let link_keys = BorshDeserialize::deserialize(buf)?;
+        let fee_recipient = BorshDeserialize::deserialize(buf)?;
+        let legacy_transactions = BorshDeserialize::deserialize(buf)?;
         Ok(Self {
             owner,
             transactions,
             groups,
             group_members,
             file_metadata,
+            group_signing_keys,
+            redeemed_invitations,
+            group_managers,
+            membership_requests,
+            pending_requests,
+            ft_gates,
+            group_policies,
+            account_first_seen,
+            membership_prices,
+            proceeds,
+            protocol_fee_bps,
+            file_prices,
+            file_entitlements,
+            file_sale_splits,
+            storage_quotas,
+            group_usage,
+            member_usage,
+            attestation_keys,
+            pinning_providers,
+            file_pins,
+            replication_targets,
+            chunk_roots,
+            challenges,
+            next_challenge_id,
+            key_epochs,
+            key_access_log,
+            key_access_counts,
+            rotation_jobs,
+            rotated_files,
+            rotation_previous_cids,
+            rotation_previous_keys,
+            rotation_changed_files,
+            share_grants,
+            revoked_shares,
+            delegations,
+            member_delegates,
+            group_parents,
+            group_children,
+            inheritance_opt_outs,
+            file_links,
+            pause_flags,
+            council,
+            proposals,
+            next_proposal_id,
+            staged_upgrade,
+            upgrade_delay_ns,
+            pending_upgrade_delay,
+            nft_contract,
+            group_linked_files,
+            pending_file_links,
+            membership_tokens,
+            token_groups,
+            member_token_counts,
+            soulbound_groups,
+            token_only_members,
+            purchase_keys,
+            link_keys,
+            fee_recipient,
+            legacy_transactions,
             #[cfg(test)]
             mock_promise_result: None,
         })
@@ -65,6 +326,38 @@ pub struct Transaction {
     user_id: String,
     file_hash: String,
     ipfs_hash: String,
+    #[schemars(with = "String")]
+    size_bytes: U64,
+    #[schemars(with = "String")]
+    created_at: U64, // Block timestamp in nanoseconds, 0 for records migrated from v0.2.0
+    #[schemars(with = "String")]
+    block_height: U64, // 0 for records migrated from v0.2.0
+    #[schemars(with = "Option<String>")]
+    recorded_by: Option<AccountId>, // Agent that recorded the upload, unknown for v0.2.0 records
+    file_name: Option<String>,
+    mime_type: Option<String>,
+    attestation: Option<UploadAttestation>, // Storage agent signature, none for v0.2.0 records
+    wrapped_key: Option<WrappedKey>, // Per-file data key, none when the file is encrypted with the group key directly
+    #[schemars(with = "Option<String>")]
+    release_at: Option<U64>, // Embargo end in nanoseconds, hidden from members until then. Requires a wrapped_key
+}
+
+// File data key encrypted with the group key of an epoch
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WrappedKey {
+    wrapped_key: String,
+    epoch: u64,
+}
+
+// Storage agent signature over the borsh serialization of the
+// (group_id, user_id, file_hash, ipfs_hash, size_bytes) tuple
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UploadAttestation {
+    #[schemars(with = "String")]
+    public_key: PublicKey,
+    signature: String, // Hex-encoded ed25519 signature
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
@@ -73,21 +366,707 @@ pub struct Group {
     group_key: Option<String>, // Stores the symmetric group key
 }
 
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RotationStatus {
+    InProgress,
+    Aborting, // Previous key restored, changed files still being restored
+    Completed,
+    Aborted,
+}
+
+// Re-encryption progress of a group after rotate_group_key
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RotationJob {
+    #[serde(skip)]
+    previous_key: Option<String>, // Restored on abort, never exposed in views
+    previous_epoch: u64,
+    new_epoch: u64,
+    #[schemars(with = "String")]
+    started_at: U64,
+    #[schemars(with = "String")]
+    deadline: U64,
+    total_files: u64,
+    pending_files: u64,
+    changed_files: u64, // Files changed by this rotation and not yet restored by an abort
+    status: RotationStatus,
+}
+
+// A group key fetched through request_group_key
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeyAccess {
+    #[schemars(with = "String")]
+    account_id: AccountId,
+    #[schemars(with = "String")]
+    timestamp: U64,
+    key_epoch: u64,
+}
+
+// Off-chain invitation signed with the group signing key. The signed message is the borsh
+// serialization of (contract account id, "invitation", invitation), see `signed_message`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Invitation {
+    group_id: String,
+    #[schemars(with = "Option<String>")]
+    invitee: Option<AccountId>, // None for an open invitation
+    #[schemars(with = "String")]
+    expires_at: U64, // Block timestamp in nanoseconds
+    #[schemars(with = "String")]
+    nonce: U64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SharePermission {
+    Metadata, // get_file_metadata
+    Key, // get_shared_key, the data key wrapped for the grantee
+}
+
+// Off-chain share of a single file signed with the group signing key. The signed message is the borsh
+// serialization of (contract account id, "share", capability), see `signed_message`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShareCapability {
+    trans_id: String,
+    #[schemars(with = "Option<String>")]
+    grantee: Option<AccountId>, // None for a bearer capability
+    #[schemars(with = "String")]
+    expires_at: U64, // Block timestamp in nanoseconds
+    permissions: Vec<SharePermission>,
+    #[schemars(with = "String")]
+    nonce: U64,
+    wrapped_key: Option<String>, // Data key wrapped for the grantee, required with SharePermission::Key
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShareGrant {
+    #[schemars(with = "String")]
+    expires_at: U64,
+    permissions: Vec<SharePermission>,
+    #[schemars(with = "String")]
+    nonce: U64,
+    wrapped_key: Option<String>,
+}
+
+// M-of-N signer set replacing the owner for admin actions
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Council {
+    #[schemars(with = "Vec<String>")]
+    signers: Vec<AccountId>,
+    threshold: u32,
+}
+
+// Admin actions a council can approve, each executed as a call from the contract to itself
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CouncilAction {
+    SetOwner {
+        #[schemars(with = "String")]
+        new_owner: AccountId,
+    },
+    SetCouncil {
+        #[schemars(with = "Vec<String>")]
+        signers: Vec<AccountId>,
+        threshold: u32,
+    },
+    RegisterAttestationKey {
+        #[schemars(with = "String")]
+        public_key: PublicKey,
+        #[schemars(with = "String")]
+        agent_id: AccountId,
+    },
+    RevokeAttestationKey {
+        #[schemars(with = "String")]
+        public_key: PublicKey,
+    },
+    Pause { group_id: Option<String>, scope: Option<PauseScope> },
+    Unpause { group_id: Option<String>, scope: Option<PauseScope> },
+    StoreGroupKey { group_id: String, key: String },
+    RotateGroupKey { group_id: String, new_key: String },
+    UpdateGroupFiles { group_id: String, updates: Vec<(String, String)> },
+    SetNftContract {
+        #[schemars(with = "String")]
+        nft_contract: AccountId,
+    },
+    StageUpgrade { code_hash: String },
+    CancelUpgrade,
+    SetUpgradeDelay {
+        #[schemars(with = "String")]
+        delay_ns: U64,
+    },
+    SetProtocolFee { fee_bps: u16 },
+    SetFeeRecipient {
+        #[schemars(with = "String")]
+        fee_recipient: AccountId,
+    },
+    SetGroupSigningKey {
+        group_id: String,
+        #[schemars(with = "String")]
+        public_key: PublicKey,
+    },
+    AddGroupManager {
+        group_id: String,
+        #[schemars(with = "String")]
+        manager_id: AccountId,
+    },
+    RemoveGroupManager {
+        group_id: String,
+        #[schemars(with = "String")]
+        manager_id: AccountId,
+    },
+    RevokeGroupMember {
+        group_id: String,
+        #[schemars(with = "String")]
+        user_id: AccountId,
+    },
+    SetReleaseAt {
+        trans_id: String,
+        #[schemars(with = "Option<String>")]
+        release_at: Option<U64>,
+    },
+    SetChunkRoot { trans_id: String, merkle_root: String, chunk_count: u32 },
+    ApproveFileLink { trans_id: String, group_id: String, wrapped_key: String },
+    UnlinkFile { trans_id: String, group_id: String },
+    SetFilePrice {
+        trans_id: String,
+        #[schemars(with = "Option<String>")]
+        price: Option<U128>,
+    },
+    RevokeShare {
+        group_id: String,
+        #[schemars(with = "String")]
+        nonce: U64,
+    },
+}
+
+impl CouncilAction {
+    // Method and JSON arguments of the self-call performing the action
+    fn call(&self) -> (&'static str, serde_json::Value) {
+        match self {
+            CouncilAction::SetOwner { new_owner } => ("set_owner", serde_json::json!({ "new_owner": new_owner })),
+            CouncilAction::SetCouncil { signers, threshold } => {
+                ("set_council", serde_json::json!({ "signers": signers, "threshold": threshold }))
+            }
+            CouncilAction::RegisterAttestationKey { public_key, agent_id } => {
+                ("register_attestation_key", serde_json::json!({ "public_key": public_key, "agent_id": agent_id }))
+            }
+            CouncilAction::RevokeAttestationKey { public_key } => {
+                ("revoke_attestation_key", serde_json::json!({ "public_key": public_key }))
+            }
+            CouncilAction::Pause { group_id, scope } => ("pause", serde_json::json!({ "group_id": group_id, "scope": scope })),
+            CouncilAction::Unpause { group_id, scope } => ("unpause", serde_json::json!({ "group_id": group_id, "scope": scope })),
+            CouncilAction::StoreGroupKey { group_id, key } => {
+                ("store_group_key", serde_json::json!({ "group_id": group_id, "key": key }))
+            }
+            CouncilAction::RotateGroupKey { group_id, new_key } => {
+                ("rotate_group_key", serde_json::json!({ "group_id": group_id, "new_key": new_key }))
+            }
+            CouncilAction::UpdateGroupFiles { group_id, updates } => {
+                ("update_group_files", serde_json::json!({ "group_id": group_id, "updates": updates }))
+            }
+            CouncilAction::SetNftContract { nft_contract } => {
+                ("set_nft_contract", serde_json::json!({ "nft_contract": nft_contract }))
+            }
+            CouncilAction::StageUpgrade { code_hash } => ("stage_upgrade", serde_json::json!({ "code_hash": code_hash })),
+            CouncilAction::CancelUpgrade => ("cancel_upgrade", serde_json::json!({})),
+            CouncilAction::SetUpgradeDelay { delay_ns } => ("set_upgrade_delay", serde_json::json!({ "delay_ns": delay_ns })),
+            CouncilAction::SetProtocolFee { fee_bps } => ("set_protocol_fee", serde_json::json!({ "fee_bps": fee_bps })),
+            CouncilAction::SetFeeRecipient { fee_recipient } => {
+                ("set_fee_recipient", serde_json::json!({ "fee_recipient": fee_recipient }))
+            }
+            CouncilAction::SetGroupSigningKey { group_id, public_key } => {
+                ("set_group_signing_key", serde_json::json!({ "group_id": group_id, "public_key": public_key }))
+            }
+            CouncilAction::AddGroupManager { group_id, manager_id } => {
+                ("add_group_manager", serde_json::json!({ "group_id": group_id, "manager_id": manager_id }))
+            }
+            CouncilAction::RemoveGroupManager { group_id, manager_id } => {
+                ("remove_group_manager", serde_json::json!({ "group_id": group_id, "manager_id": manager_id }))
+            }
+            CouncilAction::RevokeGroupMember { group_id, user_id } => {
+                ("revoke_group_member", serde_json::json!({ "group_id": group_id, "user_id": user_id }))
+            }
+            CouncilAction::SetReleaseAt { trans_id, release_at } => {
+                ("set_release_at", serde_json::json!({ "trans_id": trans_id, "release_at": release_at }))
+            }
+            CouncilAction::SetChunkRoot { trans_id, merkle_root, chunk_count } => (
+                "set_chunk_root",
+                serde_json::json!({ "trans_id": trans_id, "merkle_root": merkle_root, "chunk_count": chunk_count }),
+            ),
+            CouncilAction::ApproveFileLink { trans_id, group_id, wrapped_key } => (
+                "approve_file_link",
+                serde_json::json!({ "trans_id": trans_id, "group_id": group_id, "wrapped_key": wrapped_key }),
+            ),
+            CouncilAction::UnlinkFile { trans_id, group_id } => {
+                ("unlink_file", serde_json::json!({ "trans_id": trans_id, "group_id": group_id }))
+            }
+            CouncilAction::SetFilePrice { trans_id, price } => {
+                ("set_file_price", serde_json::json!({ "trans_id": trans_id, "price": price }))
+            }
+            CouncilAction::RevokeShare { group_id, nonce } => {
+                ("revoke_share", serde_json::json!({ "group_id": group_id, "nonce": nonce }))
+            }
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    action: CouncilAction,
+    #[schemars(with = "String")]
+    proposer: AccountId,
+    #[schemars(with = "Vec<String>")]
+    approvals: Vec<AccountId>,
+    #[schemars(with = "String")]
+    expires_at: U64,
+    executed: bool,
+}
+
+// Contract code announced ahead of deployment
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StagedUpgrade {
+    code_hash: String, // Hex-encoded sha256 of the wasm
+    #[schemars(with = "String")]
+    staged_at: U64,
+    #[schemars(with = "String")]
+    deployable_at: U64,
+}
+
+// Method classes that can be paused independently
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PauseScope {
+    Membership,
+    Uploads,
+    Keys,
+    Metadata,
+    Payments, // File purchases and proceeds withdrawals
+}
+
+// Access a member lends to another account, with the member's rights
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Delegation {
+    #[schemars(with = "String")]
+    delegator: AccountId,
+    #[schemars(with = "String")]
+    expires_at: U64, // Block timestamp in nanoseconds
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RequestStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MembershipRequest {
+    message: Option<String>,
+    status: RequestStatus,
+    reason: Option<String>, // Optional reason given on approval or denial
+    #[schemars(with = "String")]
+    requested_at: U64,
+}
+
+// Admit users holding at least `min_balance` of a NEP-141 token
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtGate {
+    #[schemars(with = "String")]
+    token_contract: AccountId,
+    #[schemars(with = "String")]
+    min_balance: U128,
+}
+
+// Admission rule combining several conditions.
+// NEAR exposes no account creation time, so MinAccountAge counts from the first
+// time the contract saw the account (a membership request or admission attempt).
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AccessPolicy {
+    NftOwnership {
+        #[schemars(with = "String")]
+        contract_id: AccountId, // Owns a token whose extra.group_id matches the group
+    },
+    FtBalance {
+        #[schemars(with = "String")]
+        contract_id: AccountId,
+        #[schemars(with = "String")]
+        min_balance: U128,
+    },
+    Allowlist {
+        #[schemars(with = "Vec<String>")]
+        accounts: Vec<AccountId>,
+    },
+    MinAccountAge {
+        #[schemars(with = "String")]
+        min_age: U64, // Nanoseconds
+    },
+    AllOf(Vec<AccessPolicy>),
+    AnyOf(Vec<AccessPolicy>),
+}
+
+impl AccessPolicy {
+    // Collect the conditions that need a cross-contract call, in evaluation order
+    fn remote_checks<'a>(&'a self, checks: &mut Vec<&'a AccessPolicy>) {
+        match self {
+            AccessPolicy::NftOwnership { .. } | AccessPolicy::FtBalance { .. } => checks.push(self),
+            AccessPolicy::AllOf(policies) | AccessPolicy::AnyOf(policies) => {
+                policies.iter().for_each(|policy| policy.remote_checks(checks))
+            }
+            _ => {}
+        }
+    }
+
+    fn validate(&self) {
+        match self {
+            AccessPolicy::Allowlist { accounts } => assert!(!accounts.is_empty(), "Allowlist cannot be empty"),
+            AccessPolicy::AllOf(policies) | AccessPolicy::AnyOf(policies) => {
+                assert!(!policies.is_empty(), "Policy group cannot be empty");
+                policies.iter().for_each(|policy| policy.validate());
+            }
+            _ => {}
+        }
+    }
+
+    // Every remote condition consumes one promise result, so children are always
+    // evaluated in full before combining
+    fn evaluate(
+        &self,
+        group_id: &str,
+        user_id: &AccountId,
+        account_age: u64,
+        results: &mut impl Iterator<Item = Option<Vec<u8>>>,
+    ) -> bool {
+        match self {
+            AccessPolicy::NftOwnership { .. } => results
+                .next()
+                .flatten()
+                .and_then(|value| serde_json::from_slice::<Vec<Token>>(&value).ok())
+                .is_some_and(|tokens| tokens.iter().any(|token| token_group_id(token).as_deref() == Some(group_id))),
+            AccessPolicy::FtBalance { min_balance, .. } => results
+                .next()
+                .flatten()
+                .and_then(|value| serde_json::from_slice::<U128>(&value).ok())
+                .is_some_and(|balance| balance.0 >= min_balance.0),
+            AccessPolicy::Allowlist { accounts } => accounts.contains(user_id),
+            AccessPolicy::MinAccountAge { min_age } => account_age >= min_age.0,
+            AccessPolicy::AllOf(policies) => policies
+                .iter()
+                .map(|policy| policy.evaluate(group_id, user_id, account_age, results))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .all(|passed| passed),
+            AccessPolicy::AnyOf(policies) => policies
+                .iter()
+                .map(|policy| policy.evaluate(group_id, user_id, account_age, results))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .any(|passed| passed),
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PinningProvider {
+    endpoint: String, // Pinning service API endpoint
+    #[schemars(with = "String")]
+    registered_at: U64,
+    challenges_passed: u32,
+    challenges_failed: u32,
+}
+
+// Merkle root over sha256 chunk hashes, the last node of an odd level is paired with itself
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChunkRoot {
+    merkle_root: String, // Hex-encoded sha256 root
+    chunk_count: u32,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ChallengeStatus {
+    Open,
+    Passed,
+    Failed,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Challenge {
+    trans_id: String,
+    #[schemars(with = "String")]
+    provider: AccountId,
+    chunk_indices: Vec<u32>,
+    #[schemars(with = "String")]
+    deadline: U64,
+    status: ChallengeStatus,
+}
+
+// Answer for one challenged chunk, siblings ordered from leaf to root
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChunkProof {
+    chunk: String, // Hex-encoded chunk bytes
+    siblings: Vec<String>, // Hex-encoded sibling hashes
+}
+
+// Storage limits of a group, unset limits are unbounded
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageQuota {
+    #[schemars(with = "Option<String>")]
+    max_total_bytes: Option<U64>,
+    #[schemars(with = "Option<String>")]
+    max_file_count: Option<U64>,
+    #[schemars(with = "Option<String>")]
+    max_bytes_per_member: Option<U64>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageUsage {
+    #[schemars(with = "String")]
+    total_bytes: U64,
+    #[schemars(with = "String")]
+    file_count: U64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageReport {
+    quota: StorageQuota,
+    usage: StorageUsage,
+}
+
+// State layout of v0.2.0, read by `migrate`
+#[derive(BorshSerialize, BorshDeserialize)]
+struct ContractV0 {
+    owner: AccountId,
+    transactions: IterableMap<String, TransactionV0>,
+    groups: LookupMap<String, Group>,
+    group_members: LookupMap<String, Vec<AccountId>>,
+    file_metadata: LookupMap<String, String>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct TransactionV0 {
+    group_id: String,
+    user_id: String,
+    file_hash: String,
+    ipfs_hash: String,
+}
+
 #[near_bindgen]
 impl Contract {
     #[init]
     pub fn new() -> Self {
+        write_state_version();
         Self {
             owner: env::predecessor_account_id(),
             transactions: IterableMap::new(b"t"),
             groups: LookupMap::new(b"g"),
             group_members: LookupMap::new(b"m"),
             file_metadata: LookupMap::new(b"f"),
+            group_signing_keys: LookupMap::new(b"k"),
+            redeemed_invitations: LookupSet::new(b"i"),
+            group_managers: LookupMap::new(b"a"),
+            membership_requests: LookupMap::new(b"q"),
+            pending_requests: LookupMap::new(b"p"),
+            ft_gates: LookupMap::new(b"n"),
+            group_policies: LookupMap::new(b"o"),
+            account_first_seen: LookupMap::new(b"s"),
+            membership_prices: LookupMap::new(b"c"),
+            proceeds: LookupMap::new(b"d"),
+            protocol_fee_bps: 0,
+            file_prices: LookupMap::new(b"h"),
+            file_entitlements: LookupSet::new(b"j"),
+            file_sale_splits: LookupMap::new(b"l"),
+            storage_quotas: LookupMap::new(b"u"),
+            group_usage: LookupMap::new(b"v"),
+            member_usage: LookupMap::new(b"w"),
+            attestation_keys: IterableMap::new(b"x"),
+            pinning_providers: IterableMap::new(b"y"),
+            file_pins: LookupMap::new(b"z"),
+            replication_targets: LookupMap::new(b"A"),
+            chunk_roots: LookupMap::new(b"B"),
+            challenges: LookupMap::new(b"C"),
+            next_challenge_id: 0,
+            key_epochs: LookupMap::new(b"E"),
+            key_access_log: LookupMap::new(b"F"),
+            key_access_counts: LookupMap::new(b"G"),
+            rotation_jobs: LookupMap::new(b"H"),
+            rotated_files: LookupMap::new(b"I"),
+            rotation_previous_cids: LookupMap::new(b"J"),
+            rotation_previous_keys: LookupMap::new(b"K"),
+            rotation_changed_files: LookupMap::new(b"1"),
+            share_grants: LookupMap::new(b"L"),
+            revoked_shares: LookupSet::new(b"M"),
+            delegations: LookupMap::new(b"N"),
+            member_delegates: LookupMap::new(b"O"),
+            group_parents: LookupMap::new(b"P"),
+            group_children: LookupMap::new(b"Q"),
+            inheritance_opt_outs: LookupSet::new(b"R"),
+            file_links: LookupMap::new(b"S"),
+            pause_flags: IterableSet::new(b"V"),
+            council: None,
+            proposals: LookupMap::new(b"W"),
+            next_proposal_id: 0,
+            staged_upgrade: None,
+            upgrade_delay_ns: DEFAULT_UPGRADE_DELAY_NS,
+            pending_upgrade_delay: None,
+            nft_contract: DEFAULT_NFT_CONTRACT.parse().unwrap(),
+            group_linked_files: LookupMap::new(b"T"),
+            pending_file_links: LookupSet::new(b"U"),
+            membership_tokens: NonFungibleToken::new(b"b", env::current_account_id(), Some(b"e"), Some(b"r"), None::<Vec<u8>>),
+            token_groups: LookupMap::new(b"X"),
+            member_token_counts: LookupMap::new(b"Y"),
+            soulbound_groups: LookupSet::new(b"Z"),
+            token_only_members: LookupSet::new(b"D"),
+            purchase_keys: LookupMap::new(b"0"),
+            link_keys: LookupMap::new(b"2"),
+            fee_recipient: env::predecessor_account_id(),
+            legacy_transactions: None,
+            #[cfg(test)]
+            mock_promise_result: None,
+        }
+    }
+
+    // Upgrade the stored state to the current layout, leaving current state untouched so every
+    // deploy can chain it. v0.2.0 transactions are re-encoded afterwards by migrate_transactions.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let version = env::storage_read(STATE_VERSION_KEY).map(|bytes| u32::from_le_bytes(bytes.try_into().expect("Invalid state version")));
+        if version == Some(STATE_VERSION) {
+            return env::state_read().expect("Failed to read contract state");
+        }
+        assert!(version.is_none(), "Unknown state version");
+        let old: ContractV0 = env::state_read().expect("Failed to read contract state");
+        write_state_version();
+        Self {
+            owner: old.owner.clone(),
+            transactions: IterableMap::new(b"3"), // The v0.2.0 records keep "t" until re-encoded
+            groups: old.groups,
+            group_members: old.group_members,
+            file_metadata: old.file_metadata,
+            group_signing_keys: LookupMap::new(b"k"),
+            redeemed_invitations: LookupSet::new(b"i"),
+            group_managers: LookupMap::new(b"a"),
+            membership_requests: LookupMap::new(b"q"),
+            pending_requests: LookupMap::new(b"p"),
+            ft_gates: LookupMap::new(b"n"),
+            group_policies: LookupMap::new(b"o"),
+            account_first_seen: LookupMap::new(b"s"),
+            membership_prices: LookupMap::new(b"c"),
+            proceeds: LookupMap::new(b"d"),
+            protocol_fee_bps: 0,
+            file_prices: LookupMap::new(b"h"),
+            file_entitlements: LookupSet::new(b"j"),
+            file_sale_splits: LookupMap::new(b"l"),
+            storage_quotas: LookupMap::new(b"u"),
+            group_usage: LookupMap::new(b"v"),
+            member_usage: LookupMap::new(b"w"),
+            attestation_keys: IterableMap::new(b"x"),
+            pinning_providers: IterableMap::new(b"y"),
+            file_pins: LookupMap::new(b"z"),
+            replication_targets: LookupMap::new(b"A"),
+            chunk_roots: LookupMap::new(b"B"),
+            challenges: LookupMap::new(b"C"),
+            next_challenge_id: 0,
+            key_epochs: LookupMap::new(b"E"),
+            key_access_log: LookupMap::new(b"F"),
+            key_access_counts: LookupMap::new(b"G"),
+            rotation_jobs: LookupMap::new(b"H"),
+            rotated_files: LookupMap::new(b"I"),
+            rotation_previous_cids: LookupMap::new(b"J"),
+            rotation_previous_keys: LookupMap::new(b"K"),
+            rotation_changed_files: LookupMap::new(b"1"),
+            share_grants: LookupMap::new(b"L"),
+            revoked_shares: LookupSet::new(b"M"),
+            delegations: LookupMap::new(b"N"),
+            member_delegates: LookupMap::new(b"O"),
+            group_parents: LookupMap::new(b"P"),
+            group_children: LookupMap::new(b"Q"),
+            inheritance_opt_outs: LookupSet::new(b"R"),
+            file_links: LookupMap::new(b"S"),
+            pause_flags: IterableSet::new(b"V"),
+            council: None,
+            proposals: LookupMap::new(b"W"),
+            next_proposal_id: 0,
+            staged_upgrade: None,
+            upgrade_delay_ns: DEFAULT_UPGRADE_DELAY_NS,
+            pending_upgrade_delay: None,
+            nft_contract: DEFAULT_NFT_CONTRACT.parse().unwrap(),
+            group_linked_files: LookupMap::new(b"T"),
+            pending_file_links: LookupSet::new(b"U"),
+            membership_tokens: NonFungibleToken::new(b"b", env::current_account_id(), Some(b"e"), Some(b"r"), None::<Vec<u8>>),
+            token_groups: LookupMap::new(b"X"),
+            member_token_counts: LookupMap::new(b"Y"),
+            soulbound_groups: LookupSet::new(b"Z"),
+            token_only_members: LookupSet::new(b"D"),
+            purchase_keys: LookupMap::new(b"0"),
+            link_keys: LookupMap::new(b"2"),
+            fee_recipient: old.owner,
+            legacy_transactions: Some(old.transactions),
             #[cfg(test)]
             mock_promise_result: None,
         }
     }
 
+    // Upgrade: Re-encode up to `limit` v0.2.0 transactions, callable by anyone until none are left.
+    // Returns the number still to migrate.
+    pub fn migrate_transactions(&mut self, limit: Option<u64>) -> U64 {
+        let legacy = self.legacy_transactions.as_mut().expect("No transactions to migrate");
+        let trans_ids: Vec<String> = legacy.keys().take(limit.unwrap_or(50) as usize).cloned().collect();
+        let mut records = Vec::new();
+        for trans_id in trans_ids {
+            let tx = legacy.remove(&trans_id).unwrap();
+            records.push((trans_id, tx));
+        }
+        let remaining = legacy.len() as u64;
+        for (trans_id, tx) in records {
+            self.group_usage.entry(tx.group_id.clone()).or_default().file_count.0 += 1;
+            if let Ok(user_id) = tx.user_id.parse::<AccountId>() {
+                self.member_usage.entry((tx.group_id.clone(), user_id)).or_default().file_count.0 += 1;
+            }
+            self.transactions.insert(trans_id, Transaction {
+                group_id: tx.group_id,
+                user_id: tx.user_id,
+                file_hash: tx.file_hash,
+                ipfs_hash: tx.ipfs_hash,
+                size_bytes: U64(0), // Unknown for v0.2.0 records
+                created_at: U64(0),
+                block_height: U64(0),
+                recorded_by: None,
+                file_name: None,
+                mime_type: None,
+                attestation: None,
+                wrapped_key: None,
+                release_at: None,
+            });
+        }
+        if remaining == 0 {
+            self.legacy_transactions = None;
+            log!("Transaction migration complete");
+        } else {
+            log!("{} transactions left to migrate", remaining);
+        }
+        U64(remaining)
+    }
+
     #[cfg(test)]
     pub fn set_mock_promise_result(&mut self, result: Vec<Token>) {
         self.mock_promise_result = Some(result);
@@ -96,10 +1075,12 @@ impl Contract {
     // Step 1: Register a new group
     #[payable]
     pub fn register_group(&mut self, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
         assert!(!self.groups.contains_key(&group_id), "Group already exists");
         let caller = env::predecessor_account_id();
+        // The owner keeps this right under a council, groups registered by a proposal would be owned by the contract
         assert!(
-            caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            caller == self.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only contract owner or devbot agents can register a group"
         );
         let group = Group {
@@ -113,28 +1094,60 @@ impl Contract {
 
     // Step 3: Record a transaction
     #[payable]
+    #[allow(clippy::too_many_arguments)]
     pub fn record_transaction(
         &mut self,
         group_id: String,
         user_id: AccountId,
         file_hash: String,
         ipfs_hash: String,
+        size_bytes: U64,
+        file_name: Option<String>,
+        mime_type: Option<String>,
+        attestation: UploadAttestation,
+        wrapped_key: Option<String>,
+        release_at: Option<U64>,
     ) -> String {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
         assert!(self.groups.contains_key(&group_id), "Group not found");
         assert!(self.is_authorized(group_id.clone(), user_id.clone()), "User not authorized");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only devbot agents can record transactions"
         );
+        // An abort would discard the key new uploads are encrypted under
+        assert!(!self.rotation_in_progress(&group_id), "Key rotation in progress");
+        assert!(self.attestation_keys.contains_key(&attestation.public_key), "Attestation key not registered");
+        let claim = borsh::to_vec(&(&group_id, &user_id, &file_hash, &ipfs_hash, size_bytes.0)).expect("Failed to serialize upload claim");
+        assert!(verify_signature(&attestation.public_key, &claim, &attestation.signature), "Invalid upload attestation");
+        let created_at = env::block_timestamp();
         let trans_id = hex::encode(env::sha256(
-            &(group_id.clone() + &user_id.to_string() + &file_hash + &ipfs_hash + &env::block_timestamp().to_string()).into_bytes()
+            (group_id.clone() + user_id.as_str() + &file_hash + &ipfs_hash + &created_at.to_string()).into_bytes()
         ));
+        let wrapped_key = wrapped_key.map(|wrapped_key| {
+            assert!(!wrapped_key.is_empty(), "Wrapped key cannot be empty");
+            WrappedKey { wrapped_key, epoch: self.current_key_epoch(&group_id) }
+        });
+        assert!(
+            wrapped_key.is_some() || release_at.is_none_or(|release_at| created_at >= release_at.0),
+            "Embargoed files need a per-file wrapped key"
+        );
+        self.charge_quota(&group_id, &user_id, size_bytes.0);
         let tx = Transaction {
             group_id,
             user_id: user_id.to_string(),
             file_hash,
             ipfs_hash,
+            size_bytes,
+            created_at: U64(created_at),
+            block_height: U64(env::block_height()),
+            recorded_by: Some(caller),
+            file_name,
+            mime_type,
+            attestation: Some(attestation),
+            wrapped_key,
+            release_at,
         };
         self.transactions.insert(trans_id.clone(), tx);
         log!("Transaction recorded: {}", trans_id);
@@ -143,26 +1156,21 @@ impl Contract {
 
     // Step 6: Add a member to a group
     #[payable]
-    pub fn add_group_member(&mut self, group_id: String, user_id: AccountId) {
+    pub fn add_group_member(&mut self, group_id: String, user_id: AccountId) -> Promise {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
         let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only group owner, auth-agent, or devbot agents can add members"
         );
-        // Step 4: Check token ownership via cross-contract call to 1000fans.testnet
-        ext_nft::ext("1000fans.testnet".parse().unwrap())
-            .with_static_gas(Gas::from_tgas(10))
-            .nft_tokens_for_owner(user_id.clone(), None, Some(1))
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_tgas(10))
-                    .add_group_member_callback(group_id, user_id)
-            );
+        self.check_token_and_add(group_id, user_id)
     }
 
     #[private]
     pub fn add_group_member_callback(&mut self, group_id: String, user_id: AccountId) {
+        // A pause may have landed while the ownership check was in flight
+        self.assert_scope_not_paused(Some(&group_id), PauseScope::Membership);
         #[cfg(test)]
         {
             if let Some(tokens) = self.mock_promise_result.clone() {
@@ -173,22 +1181,14 @@ impl Contract {
                 let extra_json: serde_json::Value = serde_json::from_str(extra).expect("Invalid extra JSON");
                 let token_group_id = extra_json["group_id"].as_str().expect("group_id missing in token metadata");
                 assert_eq!(token_group_id, group_id, "Token group_id does not match group");
-                let members = self.group_members.get(&group_id).expect("Group not found");
-                let mut members = members.to_vec();
-                if !members.contains(&user_id) {
-                    members.push(user_id.clone());
-                    self.group_members.insert(group_id.clone(), members);
-                    log!("User {} added to group {}", user_id, group_id);
-                } else {
-                    log!("User {} is already a member of group {}", user_id, group_id);
-                }
+                self.insert_group_member(&group_id, &user_id);
                 return;
             }
         }
     
         assert_eq!(env::promise_results_count(), 1, "Expected one promise result");
-        match env::promise_result(0) {
-            PromiseResult::Successful(value) => {
+        match promise_result_value(0) {
+            Some(value) => {
                 let tokens: Vec<Token> = serde_json::from_slice(&value).expect("Invalid response");
                 assert!(!tokens.is_empty(), "User does not own a 1000fans token");
                 let token = &tokens[0];
@@ -197,144 +1197,979 @@ impl Contract {
                 let extra_json: serde_json::Value = serde_json::from_str(extra).expect("Invalid extra JSON");
                 let token_group_id = extra_json["group_id"].as_str().expect("group_id missing in token metadata");
                 assert_eq!(token_group_id, group_id, "Token group_id does not match group");
-                let members = self.group_members.get(&group_id).expect("Group not found");
-                let mut members = members.to_vec();
-                if !members.contains(&user_id) {
-                    members.push(user_id.clone());
-                    self.group_members.insert(group_id.clone(), members);
-                    log!("User {} added to group {}", user_id, group_id);
-                } else {
-                    log!("User {} is already a member of group {}", user_id, group_id);
-                }
+                self.insert_group_member(&group_id, &user_id);
             }
-            _ => env::panic_str("Failed to check token ownership"),
+            None => env::panic_str("Failed to check token ownership"),
         }
     }
 
     // Step 6: Revoke a group member
     #[payable]
     pub fn revoke_group_member(&mut self, group_id: String, user_id: AccountId) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
         let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only group owner, auth-agent, or devbot agents can revoke members"
         );
-        let members = self.group_members.get(&group_id).expect("Group not found");
-        let mut members = members.to_vec();
-        if let Some(index) = members.iter().position(|x| x == &user_id) {
-            members.remove(index);
-            self.group_members.insert(group_id.clone(), members);
-            log!("User {} revoked from group {}", user_id, group_id);
-        } else {
-            log!("User {} is not a member of group {}", user_id, group_id);
+        // Membership follows token ownership, so revoking burns the member's tokens of the group
+        let token_ids: Vec<TokenId> = self
+            .membership_tokens
+            .tokens_per_owner
+            .as_ref()
+            .and_then(|tokens_per_owner| tokens_per_owner.get(&user_id))
+            .map(|tokens| tokens.iter().filter(|token_id| self.token_groups.get(token_id) == Some(&group_id)).collect())
+            .unwrap_or_default();
+        for token_id in token_ids {
+            self.burn_token(&token_id, &group_id, &user_id, &caller);
+        }
+        if self.is_member(&group_id, &user_id) {
+            self.remove_group_member(&group_id, &user_id);
         }
     }
 
     // Steps 6-8: Check if a user is authorized to access a group
     pub fn is_authorized(&self, group_id: String, user_id: AccountId) -> bool {
-        let members = self.group_members.get(&group_id).expect("Group not found");
-        members.contains(&user_id)
+        // Walk up the hierarchy while sub-groups inherit their parent's members
+        let mut current = group_id;
+        loop {
+            if self.is_directly_authorized(&current, &user_id) {
+                return true;
+            }
+            if self.inheritance_opt_outs.contains(&current) {
+                return false;
+            }
+            match self.group_parents.get(&current) {
+                Some(parent_id) => current = parent_id.clone(),
+                None => return false,
+            }
+        }
+    }
+
+    // Sub-groups: Attach a group under a parent, or detach it with None
+    #[payable]
+    pub fn set_parent_group(&mut self, group_id: String, parent_id: Option<String>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the parent group"
+        );
+        if let Some(parent_id) = &parent_id {
+            assert!(self.groups.contains_key(parent_id), "Parent group not found");
+            assert!(
+                parent_id != &group_id && !self.ancestors(parent_id).contains(&group_id),
+                "Parent group would create a cycle"
+            );
+            assert!(
+                self.ancestors(parent_id).len() + 1 + self.subtree_height(&group_id) <= MAX_GROUP_DEPTH,
+                "Group hierarchy too deep"
+            );
+        }
+        if let Some(previous_id) = self.group_parents.remove(&group_id) {
+            if let Some(children) = self.group_children.get_mut(&previous_id) {
+                children.retain(|x| x != &group_id);
+            }
+        }
+        if let Some(parent_id) = parent_id {
+            self.group_children.entry(parent_id.clone()).or_default().push(group_id.clone());
+            self.group_parents.insert(group_id.clone(), parent_id.clone());
+            log!("Group {} is now a sub-group of {}", group_id, parent_id);
+        } else {
+            log!("Group {} detached from its parent", group_id);
+        }
+    }
+
+    // Sub-groups: Choose whether parent members can access this group
+    #[payable]
+    pub fn set_inherit_membership(&mut self, group_id: String, inherit: bool) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can change membership inheritance"
+        );
+        if inherit {
+            self.inheritance_opt_outs.remove(&group_id);
+        } else {
+            self.inheritance_opt_outs.insert(group_id.clone());
+        }
+        log!("Membership inheritance of group {} set to {}", group_id, inherit);
+    }
+
+    pub fn get_parent_group(&self, group_id: String) -> Option<String> {
+        self.group_parents.get(&group_id).cloned()
+    }
+
+    pub fn get_child_groups(&self, group_id: String) -> Vec<String> {
+        self.group_children.get(&group_id).cloned().unwrap_or_default()
+    }
+
+    // Sub-groups: Direct members plus those inherited from parent groups
+    pub fn get_effective_members(&self, group_id: String) -> Vec<AccountId> {
+        let mut members = self.group_members.get(&group_id).expect("Group not found").clone();
+        let mut current = group_id;
+        while !self.inheritance_opt_outs.contains(&current) {
+            let Some(parent_id) = self.group_parents.get(&current) else {
+                break;
+            };
+            for member in self.group_members.get(parent_id).into_iter().flatten() {
+                if !members.contains(member) {
+                    members.push(member.clone());
+                }
+            }
+            current = parent_id.clone();
+        }
+        members
+    }
+
+    // Delegation: Let another account act for the calling member until expires_at, the member pays for the storage
+    #[payable]
+    pub fn delegate_access(&mut self, group_id: String, delegate: AccountId, expires_at: U64) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(self.is_member(&group_id, &caller), "Only group members can delegate access");
+        assert!(delegate != caller, "Cannot delegate access to yourself");
+        assert!(expires_at.0 > env::block_timestamp(), "Delegation must expire in the future");
+        let key = (group_id.clone(), delegate.clone());
+        if let Some(existing) = self.delegations.get(&key) {
+            assert!(existing.delegator == caller, "Account already holds a delegation from another member");
+        }
+        let initial_storage = env::storage_usage();
+        self.delegations.insert(key, Delegation { delegator: caller.clone(), expires_at });
+        let delegates = self.member_delegates.entry((group_id.clone(), caller.clone())).or_default();
+        if !delegates.contains(&delegate) {
+            delegates.push(delegate.clone());
+        }
+        self.delegations.flush();
+        self.member_delegates.flush();
+        self.charge_storage(&caller, initial_storage);
+        log!("User {} delegated access to group {} to {}", caller, group_id, delegate);
+    }
+
+    // Delegation: Withdraw a delegation, by its delegator or the group owner
+    #[payable]
+    pub fn revoke_delegation(&mut self, group_id: String, delegate: AccountId) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let key = (group_id.clone(), delegate.clone());
+        let delegation = self.delegations.get(&key).cloned().expect("Delegation not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == delegation.delegator || caller == group.owner || self.is_admin(&caller),
+            "Only the delegator or group owner can revoke a delegation"
+        );
+        self.delegations.remove(&key);
+        if let Some(delegates) = self.member_delegates.get_mut(&(group_id.clone(), delegation.delegator)) {
+            delegates.retain(|x| x != &delegate);
+        }
+        log!("Delegation of group {} to {} revoked", group_id, delegate);
+    }
+
+    pub fn get_delegation(&self, group_id: String, delegate: AccountId) -> Option<Delegation> {
+        self.delegations.get(&(group_id, delegate)).cloned()
+    }
+
+    pub fn get_delegates(&self, group_id: String, delegator: AccountId) -> Vec<AccountId> {
+        self.member_delegates.get(&(group_id, delegator)).cloned().unwrap_or_default()
     }
 
     // Step 6: Store the group key (called by storage-agent)
     #[payable]
     pub fn store_group_key(&mut self, group_id: String, key: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
         let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only group owner or devbot agents can store group key"
         );
         assert!(!key.is_empty(), "Group key cannot be empty");
+        // Replacing a key would strand every file wrapped under it
+        assert!(group.group_key.is_none(), "Group key already set, use rotate_group_key");
         let mut group = group.clone();
         group.group_key = Some(key.clone());
+        self.bump_key_epoch(&group_id);
         self.groups.insert(group_id.clone(), group);
         log!("Group key stored for group {}", group_id);
     }
 
-    // Step 6: Retrieve the group key (called by storage-agent or auth-agent)
+    // Step 6: Retrieve the group key (called by storage-agent or auth-agent), members use request_group_key
     pub fn get_group_key(&self, group_id: String, user_id: AccountId) -> String {
         let group = self.groups.get(&group_id).expect("Group not found");
         assert!(self.is_authorized(group_id.clone(), user_id.clone()), "User not authorized");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near") || caller == user_id,
-            "Only group owner, devbot agents, or the user can retrieve the group key"
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can retrieve the group key, members use request_group_key"
         );
         group.group_key.clone().expect("No group key set")
     }
 
-    // Step 7: Retrieve a transaction
-    pub fn get_transaction(&self, trans_id: String) -> Option<Transaction> {
-        self.transactions.get(&trans_id).cloned()
-    }
-
-    // Step 15: Rotate the group key (called by storage-agent)
+    // Audit: Fetch the group key as the caller, leaving an access record paid for by the caller
     #[payable]
-    pub fn rotate_group_key(&mut self, group_id: String, new_key: String) {
+    pub fn request_group_key(&mut self, group_id: String) -> String {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
         let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near"),
-            "Only group owner or devbot agents can rotate group key"
+            caller == group.owner || self.is_authorized(group_id.clone(), caller.clone()),
+            "Only group owner or members can request the group key"
         );
-        assert!(!new_key.is_empty(), "New group key cannot be empty");
-        let mut group = group.clone();
-        group.group_key = Some(new_key.clone());
-        self.groups.insert(group_id.clone(), group);
-        log!("Group key rotated for group {}", group_id);
+        let key = group.group_key.clone().expect("No group key set");
+        let initial_storage = env::storage_usage();
+        let access = KeyAccess {
+            account_id: caller.clone(),
+            timestamp: U64(env::block_timestamp()),
+            key_epoch: self.key_epoch(&group_id),
+        };
+        let count = self.key_access_counts.get(&group_id).copied().unwrap_or(0);
+        self.key_access_log.insert((group_id.clone(), count), access);
+        self.key_access_counts.insert(group_id.clone(), count + 1);
+        self.key_access_log.flush();
+        self.key_access_counts.flush();
+        self.charge_storage(&caller, initial_storage);
+        log!("Group key of {} requested by {}", group_id, caller);
+        key
     }
 
-    // Steps 7, 10: Retrieve all transactions for a group
-    pub fn get_transactions_for_group(&self, group_id: String) -> Vec<Transaction> {
+    pub fn get_key_epoch(&self, group_id: String) -> u64 {
         assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.key_epoch(&group_id)
+    }
+
+    // Audit: Key access records of a group, oldest first
+    pub fn get_key_access_log(&self, group_id: String, from_index: Option<u64>, limit: Option<u64>) -> Vec<KeyAccess> {
+        let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_authorized(group_id.clone(), caller.clone()),
-            "Only group members, owner, or devbot agents can view transactions"
+            caller == group.owner || caller == self.owner,
+            "Only group owner can view the key access log"
         );
-        self.transactions
+        let count = self.key_access_counts.get(&group_id).copied().unwrap_or(0);
+        let start = from_index.unwrap_or(0).min(count);
+        let end = start.saturating_add(limit.unwrap_or(50)).min(count);
+        (start..end)
+            .filter_map(|index| self.key_access_log.get(&(group_id.clone(), index)).cloned())
+            .collect()
+    }
+
+    pub fn get_key_access_count(&self, group_id: String) -> u64 {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.key_access_counts.get(&group_id).copied().unwrap_or(0)
+    }
+
+    // Rotation: Close a rotation once every file has been re-encrypted
+    #[payable]
+    pub fn complete_key_rotation(&mut self, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can complete a key rotation"
+        );
+        assert!(self.rotation_in_progress(&group_id), "No key rotation in progress");
+        let job = self.rotation_jobs.get_mut(&group_id).unwrap();
+        assert!(job.status == RotationStatus::InProgress, "Key rotation is being aborted");
+        assert!(job.pending_files == 0, "{} files still pending re-encryption", job.pending_files);
+        // Replaced CIDs and keys are tagged with the epoch, so later rotations ignore them
+        job.status = RotationStatus::Completed;
+        job.previous_key = None;
+        log!("Key rotation completed for group {}", group_id);
+    }
+
+    // Rotation: Abandon a rotation. The first call restores the previous key and epoch, then each call
+    // restores up to `limit` changed files. Uploads stay blocked until the job reaches Aborted.
+    // Anyone can abort a rotation that outlived its deadline, or carry on an abort.
+    #[payable]
+    pub fn abort_key_rotation(&mut self, group_id: String, limit: Option<u64>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found").clone();
+        assert!(self.rotation_in_progress(&group_id), "No key rotation in progress");
+        let mut job = self.rotation_jobs.get(&group_id).cloned().unwrap();
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner
+                || self.is_admin(&caller)
+                || caller.as_str().ends_with(".devbot.near")
+                || env::block_timestamp() > job.deadline.0
+                || job.status == RotationStatus::Aborting,
+            "Only group owner or devbot agents can abort a key rotation before its deadline"
+        );
+        if job.status == RotationStatus::InProgress {
+            let mut group = group;
+            group.group_key = job.previous_key.take();
+            self.groups.insert(group_id.clone(), group);
+            self.key_epochs.insert(group_id.clone(), job.previous_epoch);
+            job.status = RotationStatus::Aborting;
+        }
+        let remaining = job.changed_files.saturating_sub(limit.unwrap_or(50));
+        for index in (remaining..job.changed_files).rev() {
+            let trans_id = self.rotation_changed_files.remove(&(group_id.clone(), index)).unwrap();
+            let key = (group_id.clone(), trans_id.clone());
+            if let Some((epoch, previous_cid)) = self.rotation_previous_cids.remove(&key) {
+                if epoch == job.new_epoch {
+                    self.transactions.get_mut(&trans_id).unwrap().ipfs_hash = previous_cid;
+                }
+            }
+            if let Some((epoch, previous_key)) = self.rotation_previous_keys.remove(&key) {
+                if epoch == job.new_epoch {
+                    self.transactions.get_mut(&trans_id).unwrap().wrapped_key = previous_key;
+                }
+            }
+        }
+        job.changed_files = remaining;
+        if remaining == 0 {
+            job.status = RotationStatus::Aborted;
+            log!("Key rotation aborted for group {}", group_id);
+        } else {
+            log!("Key rotation of group {} aborting, {} files left to restore", group_id, remaining);
+        }
+        self.rotation_jobs.insert(group_id.clone(), job);
+    }
+
+    pub fn get_rotation_job(&self, group_id: String) -> Option<RotationJob> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.rotation_jobs.get(&group_id).cloned()
+    }
+
+    // Rotation: Files of the open rotation still awaiting a re-encrypted CID
+    pub fn get_rotation_pending_files(&self, group_id: String, from_index: Option<u64>, limit: Option<u64>) -> Vec<String> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        let Some(epoch) = self.open_rotation_epoch(&group_id) else {
+            return vec![];
+        };
+        self.transactions
             .iter()
-            .filter(|(_, tx)| tx.group_id == group_id)
-            .map(|(_, tx)| tx.clone())
+            .filter(|(trans_id, tx)| {
+                tx.group_id == group_id && self.rotated_files.get(&(group_id.clone(), (*trans_id).clone())) != Some(&epoch)
+            })
+            .map(|(trans_id, _)| trans_id.clone())
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(50) as usize)
             .collect()
     }
 
-    // Step 10: Update IPFS hashes after key rotation
+    // Step 7: Retrieve a transaction, embargoed files stay hidden until their release time
+    pub fn get_transaction(&self, trans_id: String) -> Option<Transaction> {
+        self.transactions
+            .get(&trans_id)
+            .filter(|tx| tx.release_at.is_none_or(|release_at| env::block_timestamp() >= release_at.0))
+            .cloned()
+    }
+
+    // Embargo: Retrieve a transaction before its release, for the group owner and agents
+    pub fn get_unreleased_transaction(&self, trans_id: String) -> Option<Transaction> {
+        let tx = self.transactions.get(&trans_id)?;
+        let caller = env::predecessor_account_id();
+        assert!(!self.is_embargoed(tx, &caller), "Only group owner or devbot agents can view unreleased files");
+        Some(tx.clone())
+    }
+
+    // Embargo: Move or lift the release time of a file. Only files with a per-file wrapped key can be
+    // embargoed, since members holding the group key could otherwise decrypt them before release.
     #[payable]
-    pub fn update_group_files(&mut self, group_id: String, new_ipfs_hashes: Vec<String>) {
+    pub fn set_release_at(&mut self, trans_id: String, release_at: Option<U64>) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let group = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the release time"
+        );
+        if release_at.is_some_and(|release_at| env::block_timestamp() < release_at.0) {
+            assert!(tx.wrapped_key.is_some(), "Embargoed files need a per-file wrapped key");
+            // An abort could restore a file without its wrapped key
+            assert!(!self.rotation_in_progress(&tx.group_id), "Key rotation in progress");
+        }
+        self.transactions.get_mut(&trans_id).unwrap().release_at = release_at;
+        log!("Release time of file {} set to {:?}", trans_id, release_at.map(|release_at| release_at.0));
+    }
+
+    // Embargo: Files of a group not yet released, for the group owner
+    pub fn get_embargoed_files(&self, group_id: String) -> Vec<(String, U64)> {
         let group = self.groups.get(&group_id).expect("Group not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near"),
-            "Only group owner or devbot agents can update group files"
+            caller == group.owner || caller == self.owner,
+            "Only group owner can view embargoed files"
         );
-        assert!(!new_ipfs_hashes.is_empty(), "New IPFS hashes cannot be empty");
-        let transactions: Vec<(String, Transaction)> = self.transactions
+        let now = env::block_timestamp();
+        self.transactions
             .iter()
             .filter(|(_, tx)| tx.group_id == group_id)
-            .map(|(trans_id, tx)| (trans_id.clone(), tx.clone()))
+            .filter_map(|(trans_id, tx)| tx.release_at.filter(|release_at| release_at.0 > now).map(|release_at| (trans_id.clone(), release_at)))
+            .collect()
+    }
+
+    // Step 15: Rotate the group key (called by storage-agent)
+    #[payable]
+    pub fn rotate_group_key(&mut self, group_id: String, new_key: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can rotate group key"
+        );
+        assert!(!new_key.is_empty(), "New group key cannot be empty");
+        assert!(!self.rotation_in_progress(&group_id), "Key rotation in progress");
+        let mut group = group.clone();
+        let previous_key = group.group_key.replace(new_key.clone());
+        let previous_epoch = self.key_epoch(&group_id);
+        self.bump_key_epoch(&group_id);
+        let new_epoch = self.key_epoch(&group_id);
+        self.groups.insert(group_id.clone(), group);
+        // Every file of the group stays pending until re-encrypted, files are marked with the new epoch as they are
+        assert!(self.legacy_transactions.is_none(), "Transaction migration in progress");
+        let total_files = self.group_usage.get(&group_id).map_or(0, |usage| usage.file_count.0);
+        let now = env::block_timestamp();
+        self.rotation_jobs.insert(group_id.clone(), RotationJob {
+            previous_key,
+            previous_epoch,
+            new_epoch,
+            started_at: U64(now),
+            deadline: U64(now + ROTATION_TIMEOUT_NS),
+            total_files,
+            pending_files: total_files,
+            changed_files: 0,
+            status: RotationStatus::InProgress,
+        });
+        log!("Group key rotated for group {}", group_id);
+    }
+
+    // Steps 7, 10: Retrieve all transactions for a group
+    pub fn get_transactions_for_group(&self, group_id: String) -> Vec<Transaction> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_authorized(group_id.clone(), caller.clone()),
+            "Only group members, owner, or devbot agents can view transactions"
+        );
+        let mut transactions: Vec<Transaction> = self.transactions
+            .iter()
+            .filter(|(_, tx)| tx.group_id == group_id && !self.is_embargoed(tx, &caller))
+            .map(|(_, tx)| tx.clone())
             .collect();
-        assert_eq!(
-            transactions.len(),
-            new_ipfs_hashes.len(),
-            "Number of new IPFS hashes must match number of transactions"
+        // Files linked in from other groups follow the group's own files
+        for trans_id in self.group_linked_files.get(&group_id).into_iter().flatten() {
+            if let Some(tx) = self.transactions.get(trans_id).filter(|tx| !self.is_embargoed(tx, &caller)) {
+                transactions.push(tx.clone());
+            }
+        }
+        transactions
+    }
+
+    // Governance: Hand admin rights to another account
+    #[payable]
+    pub fn set_owner(&mut self, new_owner: AccountId) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can change the owner");
+        log!("Contract owner changed from {} to {}", self.owner, new_owner);
+        self.owner = new_owner;
+    }
+
+    // Governance: Configure the council, or dissolve it with no signers.
+    // Once a council exists only a council proposal can change it.
+    #[payable]
+    pub fn set_council(&mut self, signers: Vec<AccountId>, threshold: u32) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can configure the council");
+        if signers.is_empty() {
+            self.council = None;
+            log!("Council dissolved");
+            return;
+        }
+        let mut unique = signers.clone();
+        unique.sort();
+        unique.dedup();
+        assert!(unique.len() == signers.len(), "Duplicate council signers");
+        assert!(threshold > 0 && threshold as usize <= signers.len(), "Threshold must be between 1 and the number of signers");
+        log!("Council set to {} of {} signers", threshold, signers.len());
+        self.council = Some(Council { signers, threshold });
+    }
+
+    pub fn get_council(&self) -> Option<Council> {
+        self.council.clone()
+    }
+
+    // Governance: Propose an admin action, counting the proposer's approval
+    #[payable]
+    pub fn propose(&mut self, action: CouncilAction) -> u64 {
+        let caller = env::predecessor_account_id();
+        let council = self.council.as_ref().expect("No council configured");
+        assert!(council.signers.contains(&caller), "Only council signers can propose");
+        let proposal_id = self.next_proposal_id;
+        self.proposals.insert(proposal_id, Proposal {
+            action,
+            proposer: caller.clone(),
+            approvals: vec![caller.clone()],
+            expires_at: U64(env::block_timestamp() + PROPOSAL_TTL_NS),
+            executed: false,
+        });
+        self.next_proposal_id += 1;
+        log!("Proposal {} created by {}", proposal_id, caller);
+        proposal_id
+    }
+
+    // Governance: Approve a proposal before it expires
+    #[payable]
+    pub fn approve_proposal(&mut self, proposal_id: u64) {
+        let caller = env::predecessor_account_id();
+        let council = self.council.as_ref().expect("No council configured");
+        assert!(council.signers.contains(&caller), "Only council signers can approve");
+        let proposal = self.proposals.get_mut(&proposal_id).expect("Proposal not found");
+        assert!(!proposal.executed, "Proposal already executed");
+        assert!(env::block_timestamp() < proposal.expires_at.0, "Proposal expired");
+        assert!(!proposal.approvals.contains(&caller), "Proposal already approved by this signer");
+        proposal.approvals.push(caller.clone());
+        log!("Proposal {} approved by {}", proposal_id, caller);
+    }
+
+    // Governance: Run an approved proposal. Approvals only count from current signers.
+    #[payable]
+    pub fn execute_proposal(&mut self, proposal_id: u64) -> Promise {
+        let council = self.council.clone().expect("No council configured");
+        let proposal = self.proposals.get_mut(&proposal_id).expect("Proposal not found");
+        assert!(!proposal.executed, "Proposal already executed");
+        assert!(env::block_timestamp() < proposal.expires_at.0, "Proposal expired");
+        let approvals = proposal.approvals.iter().filter(|signer| council.signers.contains(signer)).count();
+        assert!(approvals >= council.threshold as usize, "Not enough approvals");
+        // Held while the call is in flight, so the proposal cannot run twice
+        proposal.executed = true;
+        let (method, args) = proposal.action.call();
+        log!("Executing proposal {}", proposal_id);
+        Promise::new(env::current_account_id())
+            .function_call(
+                method.to_string(),
+                args.to_string().into_bytes(),
+                NearToken::from_yoctonear(0),
+                Gas::from_tgas(50),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(5))
+                    .execute_proposal_callback(proposal_id)
+            )
+    }
+
+    // Release a proposal whose action failed, so it can be executed again before it expires
+    #[private]
+    pub fn execute_proposal_callback(&mut self, proposal_id: u64) {
+        if promise_result_value(0).is_some() {
+            log!("Proposal {} executed", proposal_id);
+        } else {
+            self.proposals.get_mut(&proposal_id).unwrap().executed = false;
+            log!("Proposal {} failed and can be executed again", proposal_id);
+        }
+    }
+
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<Proposal> {
+        self.proposals.get(&proposal_id).cloned()
+    }
+
+    // Point membership checks at another NEP-171 contract
+    #[payable]
+    pub fn set_nft_contract(&mut self, nft_contract: AccountId) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can set the NFT contract");
+        log!("NFT contract set to {}", nft_contract);
+        self.nft_contract = nft_contract;
+    }
+
+    pub fn get_nft_contract(&self) -> AccountId {
+        self.nft_contract.clone()
+    }
+
+    // Membership tokens: Mint a NEP-171 token whose holder is a member of the group
+    #[payable]
+    pub fn mint_membership_token(&mut self, group_id: String, token_id: TokenId, receiver_id: AccountId, metadata: Option<TokenMetadata>) -> Token {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner, contract owner, or devbot agents can mint membership tokens"
+        );
+        // Bind the token to its group through `extra`, keeping any other fields
+        let mut metadata = metadata.unwrap_or_default();
+        let mut extra: serde_json::Map<String, serde_json::Value> =
+            metadata.extra.as_deref().and_then(|extra| serde_json::from_str(extra).ok()).unwrap_or_default();
+        extra.insert("group_id".to_string(), group_id.clone().into());
+        metadata.extra = Some(serde_json::Value::Object(extra).to_string());
+        metadata.title.get_or_insert_with(|| format!("{} membership", group_id));
+        let initial_storage = env::storage_usage();
+        // Storage is charged below for the token and the membership records together
+        let token = self.membership_tokens.internal_mint_with_refund(token_id.clone(), receiver_id.clone(), Some(metadata), None);
+        NftMint { owner_id: &receiver_id, token_ids: &[&token_id], memo: None }.emit();
+        self.token_groups.insert(token_id.clone(), group_id.clone());
+        self.transfer_membership(&group_id, None, Some(&receiver_id));
+        self.token_groups.flush();
+        self.member_token_counts.flush();
+        self.group_members.flush();
+        self.membership_requests.flush();
+        self.pending_requests.flush();
+        self.charge_storage(&caller, initial_storage);
+        log!("Membership token {} of group {} minted for {}", token_id, group_id, receiver_id);
+        token
+    }
+
+    // Membership tokens: Burn a token, revoking the membership it granted
+    #[payable]
+    pub fn burn_membership_token(&mut self, token_id: TokenId) {
+        let group_id = self.token_groups.get(&token_id).cloned().expect("Token not found");
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let owner_id = self.membership_tokens.owner_by_id.get(&token_id).expect("Token not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == owner_id || caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only the token holder, group owner, contract owner, or devbot agents can burn membership tokens"
         );
-        for ((trans_id, mut tx), new_ipfs_hash) in transactions.into_iter().zip(new_ipfs_hashes.into_iter()) {
-            tx.ipfs_hash = new_ipfs_hash;
-            self.transactions.insert(trans_id, tx);
+        self.burn_token(&token_id, &group_id, &owner_id, &caller);
+    }
+
+    // Membership tokens: Make a group's tokens non-transferable (soulbound), or transferable again
+    #[payable]
+    pub fn set_group_soulbound(&mut self, group_id: String, soulbound: bool) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner, contract owner, or devbot agents can set soulbound mode"
+        );
+        if soulbound {
+            self.soulbound_groups.insert(group_id.clone());
+        } else {
+            self.soulbound_groups.remove(&group_id);
+        }
+        log!("Membership tokens of group {} are {}", group_id, if soulbound { "soulbound" } else { "transferable" });
+    }
+
+    pub fn is_group_soulbound(&self, group_id: String) -> bool {
+        self.soulbound_groups.contains(&group_id)
+    }
+
+    pub fn get_token_group(&self, token_id: TokenId) -> Option<String> {
+        self.token_groups.get(&token_id).cloned()
+    }
+
+    // Upgrade: Announce the hash of the next contract code, starting the timelock
+    #[payable]
+    pub fn stage_upgrade(&mut self, code_hash: String) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can stage upgrades");
+        assert!(
+            hex::decode(&code_hash).map(|hash| hash.len() == 32).unwrap_or(false),
+            "Code hash must be a hex-encoded sha256 hash"
+        );
+        let now = env::block_timestamp();
+        self.staged_upgrade = Some(StagedUpgrade {
+            code_hash: code_hash.clone(),
+            staged_at: U64(now),
+            deployable_at: U64(now + self.upgrade_delay()),
+        });
+        log!("Upgrade {} staged", code_hash);
+    }
+
+    #[payable]
+    pub fn cancel_upgrade(&mut self) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can cancel upgrades");
+        self.staged_upgrade.take().expect("No upgrade staged");
+        log!("Staged upgrade cancelled");
+    }
+
+    // Upgrade: Change the timelock of upgrades staged from now on.
+    // A longer delay applies at once, a shorter one only after the current delay has passed.
+    #[payable]
+    pub fn set_upgrade_delay(&mut self, delay_ns: U64) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can set the upgrade delay");
+        assert!(delay_ns.0 >= MIN_UPGRADE_DELAY_NS, "Upgrade delay is below the minimum of {} ns", MIN_UPGRADE_DELAY_NS);
+        let current = self.upgrade_delay();
+        self.upgrade_delay_ns = current;
+        if delay_ns.0 >= current {
+            self.upgrade_delay_ns = delay_ns.0;
+            self.pending_upgrade_delay = None;
+            log!("Upgrade delay set to {} ns", delay_ns.0);
+        } else {
+            let effective_at = env::block_timestamp() + current;
+            self.pending_upgrade_delay = Some((delay_ns.0, effective_at));
+            log!("Upgrade delay lowers to {} ns at {}", delay_ns.0, effective_at);
+        }
+    }
+
+    // Upgrade: Deploy the staged code, passed as the raw call input, then run migrate.
+    // Anyone can deploy once the timelock passed since the code must match the staged hash.
+    #[payable]
+    pub fn deploy_upgrade(&mut self) -> Promise {
+        let staged = self.staged_upgrade.clone().expect("No upgrade staged");
+        assert!(env::block_timestamp() >= staged.deployable_at.0, "Upgrade timelock has not passed");
+        let code = env::input().expect("Contract code missing from input");
+        assert!(hex::encode(env::sha256(&code)) == staged.code_hash, "Code does not match the staged hash");
+        self.staged_upgrade = None;
+        log!("Deploying upgrade {}", staged.code_hash);
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), vec![], NearToken::from_yoctonear(0), Gas::from_tgas(100))
+    }
+
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+        self.staged_upgrade.clone()
+    }
+
+    pub fn get_upgrade_delay(&self) -> U64 {
+        U64(self.upgrade_delay())
+    }
+
+    // Upgrade: A lowered delay and when it takes effect
+    pub fn get_pending_upgrade_delay(&self) -> Option<(U64, U64)> {
+        self.pending_upgrade_delay
+            .filter(|(_, effective_at)| env::block_timestamp() < *effective_at)
+            .map(|(delay_ns, effective_at)| (U64(delay_ns), U64(effective_at)))
+    }
+
+    // Pause: Stop a method class, a group, or everything with (None, None)
+    #[payable]
+    pub fn pause(&mut self, group_id: Option<String>, scope: Option<PauseScope>) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can pause");
+        if let Some(group_id) = &group_id {
+            assert!(self.groups.contains_key(group_id), "Group not found");
+        }
+        self.pause_flags.insert((group_id.clone(), scope));
+        emit_event("pause", serde_json::json!({ "group_id": group_id, "scope": scope }));
+    }
+
+    #[payable]
+    pub fn unpause(&mut self, group_id: Option<String>, scope: Option<PauseScope>) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can unpause");
+        assert!(self.pause_flags.remove(&(group_id.clone(), scope)), "Not paused");
+        emit_event("unpause", serde_json::json!({ "group_id": group_id, "scope": scope }));
+    }
+
+    // Pause: Active pause flags as (group_id, scope), None meaning all
+    pub fn get_pause_status(&self) -> Vec<(Option<String>, Option<PauseScope>)> {
+        self.pause_flags.iter().cloned().collect()
+    }
+
+    // Links: Ask to list a file of another group in this group, without re-uploading it
+    #[payable]
+    pub fn request_file_link(&mut self, trans_id: String, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can request file links"
+        );
+        assert!(tx.group_id != group_id, "File already belongs to this group");
+        assert!(!self.get_file_links(trans_id.clone()).contains(&group_id), "File already linked to this group");
+        self.pending_file_links.insert((trans_id.clone(), group_id.clone()));
+        log!("Link of file {} into group {} requested", trans_id, group_id);
+    }
+
+    // Links: Consent to a link request, by the owner of the file's origin group. wrapped_key is the
+    // file's data key wrapped under the target group's current key, so its members can decrypt it.
+    #[payable]
+    pub fn approve_file_link(&mut self, trans_id: String, group_id: String, wrapped_key: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let origin = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == origin.owner || self.is_admin(&caller),
+            "Only the origin group owner can approve file links"
+        );
+        // Files encrypted with the origin group key directly cannot be shared without that key
+        assert!(tx.wrapped_key.is_some(), "Linked files need a per-file wrapped key");
+        assert!(self.pending_file_links.remove(&(trans_id.clone(), group_id.clone())), "File link not requested");
+        self.store_link_key(&trans_id, &group_id, wrapped_key);
+        self.file_links.entry(trans_id.clone()).or_default().push(group_id.clone());
+        self.group_linked_files.entry(group_id.clone()).or_default().push(trans_id.clone());
+        log!("File {} linked into group {}", trans_id, group_id);
+    }
+
+    // Links: Re-wrap a linked file's data key after the target group's key rotated or the file was re-encrypted
+    #[payable]
+    pub fn submit_link_key(&mut self, trans_id: String, group_id: String, wrapped_key: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let origin = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == origin.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only the origin group owner or devbot agents can submit link keys"
+        );
+        assert!(self.get_file_links(trans_id.clone()).contains(&group_id), "File is not linked to this group");
+        self.store_link_key(&trans_id, &group_id, wrapped_key);
+        log!("Link key of file {} for group {} updated", trans_id, group_id);
+    }
+
+    // Links: Data key of a linked file wrapped for the target group. None once the target group's key
+    // rotated or the file was re-encrypted, until a new link key is submitted.
+    pub fn get_link_key(&self, trans_id: String, group_id: String) -> Option<WrappedKey> {
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_authorized(group_id.clone(), caller.clone()),
+            "Only group members, owner, or devbot agents can view link keys"
+        );
+        assert!(!self.is_embargoed(tx, &caller), "File is under embargo");
+        let (ipfs_hash, wrapped_key) = self.link_keys.get(&(trans_id, group_id.clone()))?;
+        (ipfs_hash == &tx.ipfs_hash && wrapped_key.epoch == self.key_epoch(&group_id)).then(|| wrapped_key.clone())
+    }
+
+    // Links: Remove a linked file from a group, by either group's owner. The origin group is unaffected.
+    #[payable]
+    pub fn unlink_file(&mut self, trans_id: String, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let origin = self.groups.get(&tx.group_id).expect("Group not found");
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == origin.owner || caller == group.owner || self.is_admin(&caller),
+            "Only group owners can unlink files"
+        );
+        let links = self.file_links.get_mut(&trans_id).expect("File is not linked to this group");
+        assert!(links.contains(&group_id), "File is not linked to this group");
+        links.retain(|x| x != &group_id);
+        if let Some(linked) = self.group_linked_files.get_mut(&group_id) {
+            linked.retain(|x| x != &trans_id);
+        }
+        self.link_keys.remove(&(trans_id.clone(), group_id.clone()));
+        log!("File {} unlinked from group {}", trans_id, group_id);
+    }
+
+    // Links: Groups a file is linked into besides its origin group
+    pub fn get_file_links(&self, trans_id: String) -> Vec<String> {
+        self.file_links.get(&trans_id).cloned().unwrap_or_default()
+    }
+
+    // Links: Files of other groups linked into a group
+    pub fn get_linked_files(&self, group_id: String) -> Vec<String> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.group_linked_files.get(&group_id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_file_link_pending(&self, trans_id: String, group_id: String) -> bool {
+        self.pending_file_links.contains(&(trans_id, group_id))
+    }
+
+    // Step 10: Update IPFS hashes after key rotation, as (trans_id, new_ipfs_hash) pairs.
+    // Batches may cover any subset of the group's files.
+    #[payable]
+    pub fn update_group_files(&mut self, group_id: String, updates: Vec<(String, String)>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can update group files"
+        );
+        assert!(!updates.is_empty(), "New IPFS hashes cannot be empty");
+        assert!(!self.rotation_aborting(&group_id), "Key rotation is being aborted");
+        // Validate the whole batch before writing any of it
+        for (trans_id, new_ipfs_hash) in &updates {
+            let tx = self.transactions.get(trans_id).expect("Transaction not found");
+            assert!(tx.group_id == group_id, "Transaction {} does not belong to group {}", trans_id, group_id);
+            assert!(!new_ipfs_hash.is_empty(), "New IPFS hash cannot be empty");
+        }
+        let count = updates.len();
+        let rotation_epoch = self.open_rotation_epoch(&group_id);
+        for (trans_id, new_ipfs_hash) in updates {
+            let tx = self.transactions.get_mut(&trans_id).unwrap();
+            let previous_cid = std::mem::replace(&mut tx.ipfs_hash, new_ipfs_hash);
+            if let Some(epoch) = rotation_epoch {
+                let key = (group_id.clone(), trans_id);
+                // Keep the CID from before the rotation when a file is updated twice
+                if self.rotation_previous_cids.get(&key).is_none_or(|(tagged, _)| *tagged != epoch) {
+                    self.rotation_previous_cids.insert(key.clone(), (epoch, previous_cid));
+                }
+                self.settle_rotation_file(&key, epoch);
+            }
+        }
+        log!("IPFS hashes updated for {} files of group {}", count, group_id);
+    }
+
+    // Key hierarchy: Set file data keys wrapped under the current group key, as (trans_id, wrapped_key) pairs.
+    // During a rotation this re-keys files without re-encrypting their content.
+    #[payable]
+    pub fn submit_wrapped_keys(&mut self, group_id: String, keys: Vec<(String, String)>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can submit wrapped keys"
+        );
+        assert!(!keys.is_empty(), "Wrapped keys cannot be empty");
+        assert!(!self.rotation_aborting(&group_id), "Key rotation is being aborted");
+        let epoch = self.current_key_epoch(&group_id);
+        // Validate the whole batch before writing any of it
+        for (trans_id, wrapped_key) in &keys {
+            let tx = self.transactions.get(trans_id).expect("Transaction not found");
+            assert!(tx.group_id == group_id, "Transaction {} does not belong to group {}", trans_id, group_id);
+            assert!(!wrapped_key.is_empty(), "Wrapped key cannot be empty");
         }
-        log!("IPFS hashes updated for group {}", group_id);
+        let count = keys.len();
+        let rotation_epoch = self.open_rotation_epoch(&group_id);
+        for (trans_id, wrapped_key) in keys {
+            let tx = self.transactions.get_mut(&trans_id).unwrap();
+            let previous_key = tx.wrapped_key.replace(WrappedKey { wrapped_key, epoch });
+            if let Some(rotation_epoch) = rotation_epoch {
+                let key = (group_id.clone(), trans_id);
+                if self.rotation_previous_keys.get(&key).is_none_or(|(tagged, _)| *tagged != rotation_epoch) {
+                    self.rotation_previous_keys.insert(key.clone(), (rotation_epoch, previous_key));
+                }
+                self.settle_rotation_file(&key, rotation_epoch);
+            }
+        }
+        log!("Wrapped keys submitted for {} files of group {} at epoch {}", count, group_id, epoch);
+    }
+
+    // Key hierarchy: Wrapped data key of a file, for anyone who can access it
+    pub fn get_wrapped_key(&self, trans_id: String) -> Option<WrappedKey> {
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner
+                || caller.as_str().ends_with(".devbot.near")
+                || self.has_file_access(&trans_id, tx, &caller),
+            "Only authorized users can view wrapped keys"
+        );
+        assert!(!self.is_embargoed(tx, &caller), "File is under embargo");
+        tx.wrapped_key.clone()
+    }
+
+    // Key hierarchy: Wrapped data keys of a group's files
+    pub fn get_wrapped_keys(&self, group_id: String, from_index: Option<u64>, limit: Option<u64>) -> Vec<(String, WrappedKey)> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_authorized(group_id.clone(), caller.clone()),
+            "Only group members, owner, or devbot agents can view wrapped keys"
+        );
+        self.transactions
+            .iter()
+            .filter(|(_, tx)| tx.group_id == group_id && !self.is_embargoed(tx, &caller))
+            .filter_map(|(trans_id, tx)| tx.wrapped_key.clone().map(|wrapped_key| (trans_id.clone(), wrapped_key)))
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(50) as usize)
+            .collect()
     }
 
     // AI Enhancement: Store file metadata
     #[payable]
     pub fn store_file_metadata(&mut self, trans_id: String, metadata: String) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Metadata);
         assert!(self.transactions.contains_key(&trans_id), "Transaction not found");
         let caller = env::predecessor_account_id();
         assert!(
-            caller == self.owner || caller.as_str().ends_with(".devbot.near"),
+            self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
             "Only devbot agents can store file metadata"
         );
         assert!(!metadata.is_empty(), "Metadata cannot be empty");
@@ -348,12 +2183,1410 @@ impl Contract {
         let caller = env::predecessor_account_id();
         let tx = self.transactions.get(&trans_id).expect("Transaction not found");
         assert!(
-            caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_authorized(tx.group_id.clone(), caller.clone()),
+            caller == self.owner
+                || caller.as_str().ends_with(".devbot.near")
+                || self.has_file_access(&trans_id, tx, &caller)
+                || self.has_share(&trans_id, tx, &caller, SharePermission::Metadata),
             "Only group members, owner, or devbot agents can view metadata"
         );
+        assert!(!self.is_embargoed(tx, &caller), "File is under embargo");
         self.file_metadata.get(&trans_id).cloned()
     }
-}
+
+    // FT gating: Admit members by NEP-141 balance instead of the 1000fans token
+    #[payable]
+    pub fn set_ft_gate(&mut self, group_id: String, token_contract: AccountId, min_balance: U128) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can configure token gating"
+        );
+        assert!(min_balance.0 > 0, "Minimum balance must be positive");
+        self.ft_gates.insert(group_id.clone(), FtGate { token_contract: token_contract.clone(), min_balance });
+        log!("Group {} gated on {} of {}", group_id, min_balance.0, token_contract);
+    }
+
+    // FT gating: Go back to the default 1000fans token check
+    #[payable]
+    pub fn remove_ft_gate(&mut self, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can configure token gating"
+        );
+        self.ft_gates.remove(&group_id);
+        log!("Token gating removed for group {}", group_id);
+    }
+
+    // FT gating: Retrieve the fungible token rule of a group
+    pub fn get_ft_gate(&self, group_id: String) -> Option<FtGate> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.ft_gates.get(&group_id).cloned()
+    }
+
+    #[private]
+    pub fn add_group_member_ft_callback(&mut self, group_id: String, user_id: AccountId, min_balance: U128) {
+        self.assert_scope_not_paused(Some(&group_id), PauseScope::Membership);
+        assert_eq!(env::promise_results_count(), 1, "Expected one promise result");
+        let value = promise_result_value(0).unwrap_or_else(|| env::panic_str("Failed to check token balance"));
+        let balance: U128 = serde_json::from_slice(&value).expect("Invalid response");
+        assert!(balance.0 >= min_balance.0, "User does not hold enough tokens");
+        self.insert_group_member(&group_id, &user_id);
+    }
+
+    // Access policies: Set a composite admission rule, taking precedence over token gating
+    #[payable]
+    pub fn set_group_policy(&mut self, group_id: String, policy: AccessPolicy) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can configure the access policy"
+        );
+        policy.validate();
+        let mut checks = Vec::new();
+        policy.remote_checks(&mut checks);
+        assert!(checks.len() <= MAX_POLICY_CHECKS, "Too many cross-contract checks in policy");
+        self.group_policies.insert(group_id.clone(), policy);
+        log!("Access policy set for group {}", group_id);
+    }
+
+    // Access policies: Remove the composite admission rule
+    #[payable]
+    pub fn remove_group_policy(&mut self, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can configure the access policy"
+        );
+        self.group_policies.remove(&group_id);
+        log!("Access policy removed for group {}", group_id);
+    }
+
+    // Access policies: Retrieve the composite admission rule of a group
+    pub fn get_group_policy(&self, group_id: String) -> Option<AccessPolicy> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.group_policies.get(&group_id).cloned()
+    }
+
+    #[private]
+    pub fn add_group_member_policy_callback(&mut self, group_id: String, user_id: AccountId, policy: AccessPolicy) {
+        self.assert_scope_not_paused(Some(&group_id), PauseScope::Membership);
+        let mut checks = Vec::new();
+        policy.remote_checks(&mut checks);
+        assert_eq!(env::promise_results_count(), checks.len() as u64, "Unexpected number of promise results");
+        let account_age = self
+            .account_first_seen
+            .get(&user_id)
+            .map_or(0, |first_seen| env::block_timestamp().saturating_sub(*first_seen));
+        let mut results = (0..env::promise_results_count()).map(promise_result_value);
+        assert!(
+            policy.evaluate(&group_id, &user_id, account_age, &mut results),
+            "User does not satisfy the group access policy"
+        );
+        self.insert_group_member(&group_id, &user_id);
+    }
+
+    // Paid membership: Set the price of a group in yoctoNEAR, or None to stop selling
+    #[payable]
+    pub fn set_membership_price(&mut self, group_id: String, price: Option<U128>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the membership price"
+        );
+        match price {
+            Some(price) => {
+                assert!(price.0 > 0, "Membership price must be positive");
+                self.membership_prices.insert(group_id.clone(), price.0);
+                log!("Membership price of group {} set to {}", group_id, price.0);
+            }
+            None => {
+                self.membership_prices.remove(&group_id);
+                log!("Membership sales closed for group {}", group_id);
+            }
+        }
+    }
+
+    // Paid membership: Retrieve the price of a group
+    pub fn get_membership_price(&self, group_id: String) -> Option<U128> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.membership_prices.get(&group_id).map(|price| U128(*price))
+    }
+
+    // Paid membership: Join a group by paying its price, excess deposit is refunded
+    #[payable]
+    pub fn buy_membership(&mut self, group_id: String) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found").clone();
+        let price = *self.membership_prices.get(&group_id).expect("Group membership is not for sale");
+        let caller = env::predecessor_account_id();
+        assert!(!self.is_member(&group_id, &caller), "User is already a member");
+        let deposit = env::attached_deposit().as_yoctonear();
+        assert!(deposit >= price, "Attached deposit is below the membership price");
+        self.credit_sale(price, &[(group.owner, MAX_BPS)]);
+        self.insert_group_member(&group_id, &caller);
+        if deposit > price {
+            Promise::new(caller).transfer(NearToken::from_yoctonear(deposit - price)).detach();
+        }
+    }
+
+    // Attestations: Register a storage agent key allowed to vouch for uploads
+    #[payable]
+    pub fn register_attestation_key(&mut self, public_key: PublicKey, agent_id: AccountId) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can manage attestation keys");
+        assert!(public_key.curve_type() == CurveType::ED25519, "Attestation key must be ed25519");
+        self.attestation_keys.insert(public_key, agent_id.clone());
+        log!("Attestation key registered for {}", agent_id);
+    }
+
+    // Attestations: Stop accepting uploads vouched by a key, past records keep their attestation
+    #[payable]
+    pub fn revoke_attestation_key(&mut self, public_key: PublicKey) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can manage attestation keys");
+        let agent_id = self.attestation_keys.remove(&public_key).expect("Attestation key not registered");
+        log!("Attestation key revoked for {}", agent_id);
+    }
+
+    // Attestations: List registered storage agent keys
+    pub fn get_attestation_keys(&self) -> Vec<(PublicKey, AccountId)> {
+        self.attestation_keys.iter().map(|(key, agent_id)| (key.clone(), agent_id.clone())).collect()
+    }
+
+    // Pinning: Register an account reporting pins for a pinning service
+    #[payable]
+    pub fn register_pinning_provider(&mut self, provider_id: AccountId, endpoint: String) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can manage pinning providers");
+        assert!(!endpoint.is_empty(), "Endpoint cannot be empty");
+        let provider = PinningProvider {
+            endpoint,
+            registered_at: U64(env::block_timestamp()),
+            challenges_passed: 0,
+            challenges_failed: 0,
+        };
+        self.pinning_providers.insert(provider_id.clone(), provider);
+        log!("Pinning provider {} registered", provider_id);
+    }
+
+    // Pinning: Remove a provider, its pins no longer count towards replication
+    #[payable]
+    pub fn remove_pinning_provider(&mut self, provider_id: AccountId) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can manage pinning providers");
+        self.pinning_providers.remove(&provider_id).expect("Pinning provider not found");
+        log!("Pinning provider {} removed", provider_id);
+    }
+
+    // Pinning: List registered providers
+    pub fn get_pinning_providers(&self) -> Vec<(AccountId, PinningProvider)> {
+        self.pinning_providers.iter().map(|(id, provider)| (id.clone(), provider.clone())).collect()
+    }
+
+    // Pinning: Report that the calling provider pinned a file
+    #[payable]
+    pub fn report_pin(&mut self, trans_id: String) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        assert!(self.transactions.contains_key(&trans_id), "Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(self.pinning_providers.contains_key(&caller), "Only registered pinning providers can report pins");
+        let mut pins = self.file_pins.get(&trans_id).cloned().unwrap_or_default();
+        if !pins.contains(&caller) {
+            pins.push(caller.clone());
+            self.file_pins.insert(trans_id.clone(), pins);
+        }
+        log!("File {} pinned by {}", trans_id, caller);
+    }
+
+    // Pinning: Report that the calling provider unpinned a file
+    #[payable]
+    pub fn report_unpin(&mut self, trans_id: String) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        assert!(self.transactions.contains_key(&trans_id), "Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(self.pinning_providers.contains_key(&caller), "Only registered pinning providers can report pins");
+        let mut pins = self.file_pins.get(&trans_id).cloned().unwrap_or_default();
+        pins.retain(|x| x != &caller);
+        self.file_pins.insert(trans_id.clone(), pins);
+        log!("File {} unpinned by {}", trans_id, caller);
+    }
+
+    // Pinning: Registered providers currently pinning a file
+    pub fn get_file_pins(&self, trans_id: String) -> Vec<AccountId> {
+        assert!(self.transactions.contains_key(&trans_id), "Transaction not found");
+        self.active_pins(&trans_id)
+    }
+
+    // Pinning: Number of registered providers currently pinning a file
+    pub fn get_replication_count(&self, trans_id: String) -> u32 {
+        self.get_file_pins(trans_id).len() as u32
+    }
+
+    // Pinning: Set the minimum number of providers that should pin each file of a group
+    #[payable]
+    pub fn set_replication_target(&mut self, group_id: String, min_replicas: u32) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the replication target"
+        );
+        self.replication_targets.insert(group_id.clone(), min_replicas);
+        log!("Replication target of group {} set to {}", group_id, min_replicas);
+    }
+
+    pub fn get_replication_target(&self, group_id: String) -> u32 {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.replication_targets.get(&group_id).copied().unwrap_or(DEFAULT_REPLICATION_TARGET)
+    }
+
+    // Pinning: Files of a group pinned by fewer providers than its target, with their replica count
+    pub fn get_under_replicated_files(&self, group_id: String, from_index: Option<u64>, limit: Option<u64>) -> Vec<(String, u32)> {
+        let target = self.get_replication_target(group_id.clone());
+        self.transactions
+            .iter()
+            .filter(|(_, tx)| tx.group_id == group_id)
+            .map(|(trans_id, _)| (trans_id.clone(), self.active_pins(trans_id).len() as u32))
+            .filter(|(_, replicas)| *replicas < target)
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(50) as usize)
+            .collect()
+    }
+
+    // Retrievability: Record the chunk Merkle root of a file so pinners can be challenged
+    #[payable]
+    pub fn set_chunk_root(&mut self, trans_id: String, merkle_root: String, chunk_count: u32) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            tx.recorded_by.as_ref() == Some(&caller) || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only the recording agent can set the chunk root"
+        );
+        assert!(chunk_count > 0, "Chunk count must be positive");
+        assert!(
+            hex::decode(&merkle_root).map(|root| root.len() == 32).unwrap_or(false),
+            "Merkle root must be a hex-encoded sha256 hash"
+        );
+        self.chunk_roots.insert(trans_id.clone(), ChunkRoot { merkle_root, chunk_count });
+        log!("Chunk root set for file {}", trans_id);
+    }
+
+    pub fn get_chunk_root(&self, trans_id: String) -> Option<ChunkRoot> {
+        self.chunk_roots.get(&trans_id).cloned()
+    }
+
+    // Retrievability: Challenge a provider pinning a file to prove it holds random chunks
+    #[payable]
+    pub fn issue_challenge(&mut self, trans_id: String, provider_id: AccountId) -> u64 {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let group = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or agents can issue challenges"
+        );
+        let chunk_root = self.chunk_roots.get(&trans_id).expect("Chunk root not set");
+        assert!(self.active_pins(&trans_id).contains(&provider_id), "Provider is not pinning this file");
+        let challenge_id = self.next_challenge_id;
+        let seed = env::random_seed();
+        let chunk_indices = (0..CHALLENGE_CHUNKS.min(chunk_root.chunk_count))
+            .map(|i| {
+                let digest = env::sha256([seed.as_slice(), &challenge_id.to_le_bytes(), &i.to_le_bytes()].concat());
+                u32::from_le_bytes(digest[..4].try_into().unwrap()) % chunk_root.chunk_count
+            })
+            .collect();
+        let challenge = Challenge {
+            trans_id: trans_id.clone(),
+            provider: provider_id.clone(),
+            chunk_indices,
+            deadline: U64(env::block_timestamp() + CHALLENGE_WINDOW_NS),
+            status: ChallengeStatus::Open,
+        };
+        self.challenges.insert(challenge_id, challenge);
+        self.next_challenge_id += 1;
+        log!("Challenge {} issued to {} for file {}", challenge_id, provider_id, trans_id);
+        challenge_id
+    }
+
+    // Retrievability: Answer a challenge with Merkle proofs for its chunks, in challenge order
+    #[payable]
+    pub fn respond_to_challenge(&mut self, challenge_id: u64, proofs: Vec<ChunkProof>) -> bool {
+        if let Some(challenge) = self.challenges.get(&challenge_id) {
+            self.assert_file_not_paused(&challenge.trans_id, PauseScope::Uploads);
+        }
+        let mut challenge = self.challenges.get(&challenge_id).cloned().expect("Challenge not found");
+        assert!(env::predecessor_account_id() == challenge.provider, "Only the challenged provider can respond");
+        assert!(challenge.status == ChallengeStatus::Open, "Challenge already settled");
+        assert!(env::block_timestamp() <= challenge.deadline.0, "Challenge expired");
+        let chunk_root = self.chunk_roots.get(&challenge.trans_id).expect("Chunk root not set");
+        let passed = proofs.len() == challenge.chunk_indices.len()
+            && challenge
+                .chunk_indices
+                .iter()
+                .zip(proofs.iter())
+                .all(|(index, proof)| verify_chunk_proof(chunk_root, *index, proof));
+        challenge.status = if passed { ChallengeStatus::Passed } else { ChallengeStatus::Failed };
+        self.settle_challenge(challenge_id, challenge);
+        passed
+    }
+
+    // Retrievability: Fail an unanswered challenge once its deadline passed
+    #[payable]
+    pub fn expire_challenge(&mut self, challenge_id: u64) {
+        if let Some(challenge) = self.challenges.get(&challenge_id) {
+            self.assert_file_not_paused(&challenge.trans_id, PauseScope::Uploads);
+        }
+        let mut challenge = self.challenges.get(&challenge_id).cloned().expect("Challenge not found");
+        assert!(challenge.status == ChallengeStatus::Open, "Challenge already settled");
+        assert!(env::block_timestamp() > challenge.deadline.0, "Challenge deadline not reached");
+        challenge.status = ChallengeStatus::Failed;
+        self.settle_challenge(challenge_id, challenge);
+    }
+
+    pub fn get_challenge(&self, challenge_id: u64) -> Option<Challenge> {
+        self.challenges.get(&challenge_id).cloned()
+    }
+
+    // Retrievability: Share of passed challenges in basis points, full standing without history
+    pub fn get_provider_standing(&self, provider_id: AccountId) -> u16 {
+        let provider = self.pinning_providers.get(&provider_id).expect("Pinning provider not found");
+        let total = provider.challenges_passed as u64 + provider.challenges_failed as u64;
+        if total == 0 {
+            return MAX_BPS;
+        }
+        (provider.challenges_passed as u64 * MAX_BPS as u64 / total) as u16
+    }
+
+    // Quotas: Limit the bytes and files a group can record
+    #[payable]
+    pub fn set_storage_quota(&mut self, group_id: String, quota: StorageQuota) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set storage quotas"
+        );
+        self.storage_quotas.insert(group_id.clone(), quota);
+        log!("Storage quota set for group {}", group_id);
+    }
+
+    // Quotas: Report group usage against its quota
+    pub fn get_storage_usage(&self, group_id: String) -> StorageReport {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        StorageReport {
+            quota: self.storage_quotas.get(&group_id).cloned().unwrap_or_default(),
+            usage: self.group_usage.get(&group_id).cloned().unwrap_or_default(),
+        }
+    }
+
+    // Quotas: Report a member's usage in a group
+    pub fn get_member_storage_usage(&self, group_id: String, user_id: AccountId) -> StorageUsage {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.member_usage.get(&(group_id, user_id)).cloned().unwrap_or_default()
+    }
+
+    // Pay-per-file: Set the price of a single file in yoctoNEAR, or None to stop selling
+    #[payable]
+    pub fn set_file_price(&mut self, trans_id: String, price: Option<U128>) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Uploads);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let group = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the file price"
+        );
+        match price {
+            Some(price) => {
+                assert!(price.0 > 0, "File price must be positive");
+                self.file_prices.insert(trans_id.clone(), price.0);
+                log!("Price of file {} set to {}", trans_id, price.0);
+            }
+            None => {
+                self.file_prices.remove(&trans_id);
+                log!("File {} is no longer for sale", trans_id);
+            }
+        }
+    }
+
+    // Pay-per-file: Retrieve the price of a file
+    pub fn get_file_price(&self, trans_id: String) -> Option<U128> {
+        assert!(self.transactions.contains_key(&trans_id), "Transaction not found");
+        self.file_prices.get(&trans_id).map(|price| U128(*price))
+    }
+
+    // Pay-per-file: Set the uploader's share of file sales, the group owner gets the rest
+    #[payable]
+    pub fn set_file_sale_split(&mut self, group_id: String, uploader_bps: u16) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Uploads);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the file sale split"
+        );
+        assert!(uploader_bps <= MAX_BPS, "Uploader share cannot exceed 100%");
+        self.file_sale_splits.insert(group_id.clone(), uploader_bps);
+        log!("Uploader share of file sales in group {} set to {} bps", group_id, uploader_bps);
+    }
+
+    // Pay-per-file: Uploader's share of file sales, 0 unless configured
+    pub fn get_file_sale_split(&self, group_id: String) -> u16 {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.file_sale_splits.get(&group_id).copied().unwrap_or(0)
+    }
+
+    // Pay-per-file: Buy permanent access to a single file, excess deposit is refunded.
+    // Buyers hold no group key, so the group owner or an agent then delivers the data key wrapped for them.
+    #[payable]
+    pub fn purchase_file(&mut self, trans_id: String) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Payments);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found").clone();
+        let price = *self.file_prices.get(&trans_id).expect("File is not for sale");
+        let group = self.groups.get(&tx.group_id).expect("Group not found").clone();
+        let caller = env::predecessor_account_id();
+        assert!(!self.has_file_access(&trans_id, &tx, &caller), "User already has access to this file");
+        let deposit = env::attached_deposit().as_yoctonear();
+        assert!(deposit >= price, "Attached deposit is below the file price");
+        let uploader: AccountId = tx.user_id.parse().expect("Invalid uploader account");
+        let uploader_bps = self.file_sale_splits.get(&tx.group_id).copied().unwrap_or(0);
+        self.credit_sale(price, &[(uploader, uploader_bps), (group.owner, MAX_BPS - uploader_bps)]);
+        self.file_entitlements.insert((trans_id.clone(), caller.clone()));
+        log!("File {} purchased by {}", trans_id, caller);
+        if deposit > price {
+            Promise::new(caller).transfer(NearToken::from_yoctonear(deposit - price)).detach();
+        }
+    }
+
+    // Pay-per-file: Check if a user can access a file as a group member or buyer
+    pub fn can_access_file(&self, trans_id: String, user_id: AccountId) -> bool {
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        self.has_file_access(&trans_id, tx, &user_id) && !self.is_embargoed(tx, &user_id)
+    }
+
+    // Pay-per-file: Hand a buyer the data key of a purchased file, wrapped for the buyer off-chain
+    #[payable]
+    pub fn deliver_file_key(&mut self, trans_id: String, buyer: AccountId, wrapped_key: String) {
+        self.assert_file_not_paused(&trans_id, PauseScope::Keys);
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let group = self.groups.get(&tx.group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near"),
+            "Only group owner or devbot agents can deliver file keys"
+        );
+        assert!(self.file_entitlements.contains(&(trans_id.clone(), buyer.clone())), "{} has not purchased this file", buyer);
+        assert!(!wrapped_key.is_empty(), "Wrapped key cannot be empty");
+        self.purchase_keys.insert((trans_id.clone(), buyer.clone()), wrapped_key);
+        log!("Key of file {} delivered to {}", trans_id, buyer);
+    }
+
+    // Pay-per-file: Data key of a file the caller bought, None until it has been delivered
+    pub fn get_purchased_key(&self, trans_id: String) -> Option<String> {
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(self.file_entitlements.contains(&(trans_id.clone(), caller.clone())), "File not purchased");
+        assert!(!self.is_embargoed(tx, &caller), "File is under embargo");
+        self.purchase_keys.get(&(trans_id, caller)).cloned()
+    }
+
+    // Paid membership: Set the protocol's cut of sales, credited to the fee recipient
+    #[payable]
+    pub fn set_protocol_fee(&mut self, fee_bps: u16) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can set the protocol fee");
+        assert!(fee_bps <= MAX_BPS, "Protocol fee cannot exceed 100%");
+        self.protocol_fee_bps = fee_bps;
+        log!("Protocol fee set to {} bps", fee_bps);
+    }
+
+    pub fn get_protocol_fee(&self) -> u16 {
+        self.protocol_fee_bps
+    }
+
+    // Paid membership: Set the account credited with the protocol fee
+    #[payable]
+    pub fn set_fee_recipient(&mut self, fee_recipient: AccountId) {
+        assert!(self.is_admin(&env::predecessor_account_id()), "Only contract owner can set the fee recipient");
+        log!("Fee recipient set to {}", fee_recipient);
+        self.fee_recipient = fee_recipient;
+    }
+
+    pub fn get_fee_recipient(&self) -> AccountId {
+        self.fee_recipient.clone()
+    }
+
+    // Paid membership: Proceeds awaiting withdrawal for an account
+    pub fn get_proceeds(&self, account_id: AccountId) -> U128 {
+        U128(self.proceeds.get(&account_id).copied().unwrap_or(0))
+    }
+
+    // Paid membership: Withdraw accumulated proceeds to the caller
+    pub fn withdraw_proceeds(&mut self) -> Promise {
+        self.assert_not_paused(None, PauseScope::Payments);
+        let caller = env::predecessor_account_id();
+        let amount = self.proceeds.remove(&caller).unwrap_or(0);
+        assert!(amount > 0, "No proceeds to withdraw");
+        log!("{} withdrew {} in proceeds", caller, amount);
+        Promise::new(caller.clone())
+            .transfer(NearToken::from_yoctonear(amount))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(5))
+                    .withdraw_proceeds_callback(caller, U128(amount))
+            )
+    }
+
+    // Re-credit proceeds whose transfer failed, so they can be withdrawn again
+    #[private]
+    pub fn withdraw_proceeds_callback(&mut self, account_id: AccountId, amount: U128) {
+        if promise_result_value(0).is_none() {
+            *self.proceeds.entry(account_id.clone()).or_insert(0) += amount.0;
+            log!("Withdrawal of {} in proceeds by {} failed, balance restored", amount.0, account_id);
+        }
+    }
+
+    // Membership requests: Add a manager who can review membership requests
+    #[payable]
+    pub fn add_group_manager(&mut self, group_id: String, manager_id: AccountId) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can manage group managers"
+        );
+        let mut managers = self.group_managers.get(&group_id).cloned().unwrap_or_default();
+        if !managers.contains(&manager_id) {
+            managers.push(manager_id.clone());
+            self.group_managers.insert(group_id.clone(), managers);
+        }
+        log!("Manager {} added to group {}", manager_id, group_id);
+    }
+
+    // Membership requests: Remove a group manager
+    #[payable]
+    pub fn remove_group_manager(&mut self, group_id: String, manager_id: AccountId) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can manage group managers"
+        );
+        let mut managers = self.group_managers.get(&group_id).cloned().unwrap_or_default();
+        managers.retain(|x| x != &manager_id);
+        self.group_managers.insert(group_id.clone(), managers);
+        log!("Manager {} removed from group {}", manager_id, group_id);
+    }
+
+    // Membership requests: List the managers of a group
+    pub fn get_group_managers(&self, group_id: String) -> Vec<AccountId> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.group_managers.get(&group_id).cloned().unwrap_or_default()
+    }
+
+    // Membership requests: Ask to join a group, the requester pays for the storage and excess deposit is refunded
+    #[payable]
+    pub fn request_membership(&mut self, group_id: String, message: Option<String>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        assert!(
+            message.as_ref().is_none_or(|message| message.chars().count() <= MAX_REQUEST_MESSAGE_LEN),
+            "Request message exceeds {} characters",
+            MAX_REQUEST_MESSAGE_LEN
+        );
+        let caller = env::predecessor_account_id();
+        assert!(!self.is_member(&group_id, &caller), "User is already a member");
+        let initial_storage = env::storage_usage();
+        self.touch_account(&caller);
+        let key = (group_id.clone(), caller.clone());
+        if let Some(request) = self.membership_requests.get(&key) {
+            assert!(request.status != RequestStatus::Pending, "Membership request already pending");
+        }
+        let request = MembershipRequest {
+            message,
+            status: RequestStatus::Pending,
+            reason: None,
+            requested_at: U64(env::block_timestamp()),
+        };
+        self.membership_requests.insert(key, request);
+        let mut pending = self.pending_requests.get(&group_id).cloned().unwrap_or_default();
+        pending.push(caller.clone());
+        self.pending_requests.insert(group_id.clone(), pending);
+        self.account_first_seen.flush();
+        self.membership_requests.flush();
+        self.pending_requests.flush();
+        self.charge_storage(&caller, initial_storage);
+        log!("Membership requested by {} for group {}", caller, group_id);
+    }
+
+    // Membership requests: Check the status of a request
+    pub fn get_membership_request(&self, group_id: String, user_id: AccountId) -> Option<MembershipRequest> {
+        self.membership_requests.get(&(group_id, user_id)).cloned()
+    }
+
+    // Membership requests: List pending requests (called by group owner or managers)
+    pub fn get_pending_requests(
+        &self,
+        group_id: String,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, MembershipRequest)> {
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || caller == self.owner || caller.as_str().ends_with(".devbot.near") || self.is_group_manager(&group_id, &caller),
+            "Only group owner, managers, or devbot agents can view membership requests"
+        );
+        let pending = self.pending_requests.get(&group_id).cloned().unwrap_or_default();
+        pending
+            .into_iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(50) as usize)
+            .map(|user_id| {
+                let request = self.membership_requests.get(&(group_id.clone(), user_id.clone())).cloned().expect("Request not found");
+                (user_id, request)
+            })
+            .collect()
+    }
+
+    // Membership requests: Approve a request, admitting the user through the token check
+    #[payable]
+    pub fn approve_membership_request(&mut self, group_id: String, user_id: AccountId, reason: Option<String>) -> Promise {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near") || self.is_group_manager(&group_id, &caller),
+            "Only group owner, managers, or devbot agents can review membership requests"
+        );
+        let key = (group_id.clone(), user_id.clone());
+        let mut request = self.membership_requests.get(&key).cloned().expect("Membership request not found");
+        assert!(request.status == RequestStatus::Pending, "Membership request is not pending");
+        request.reason = reason;
+        self.membership_requests.insert(key, request);
+        log!("Membership request of {} for group {} approved by {}", user_id, group_id, caller);
+        self.check_token_and_add(group_id, user_id)
+    }
+
+    // Membership requests: Deny a request
+    #[payable]
+    pub fn deny_membership_request(&mut self, group_id: String, user_id: AccountId, reason: Option<String>) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller) || caller.as_str().ends_with(".devbot.near") || self.is_group_manager(&group_id, &caller),
+            "Only group owner, managers, or devbot agents can review membership requests"
+        );
+        let key = (group_id.clone(), user_id.clone());
+        let mut request = self.membership_requests.get(&key).cloned().expect("Membership request not found");
+        assert!(request.status == RequestStatus::Pending, "Membership request is not pending");
+        request.status = RequestStatus::Denied;
+        request.reason = reason;
+        self.membership_requests.insert(key, request);
+        self.remove_pending_request(&group_id, &user_id);
+        log!("Membership request of {} for group {} denied by {}", user_id, group_id, caller);
+    }
+
+    // Invitations: Register the ed25519 key that signs invitations for a group
+    #[payable]
+    pub fn set_group_signing_key(&mut self, group_id: String, public_key: PublicKey) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can set the group signing key"
+        );
+        assert!(public_key.curve_type() == CurveType::ED25519, "Signing key must be ed25519");
+        self.group_signing_keys.insert(group_id.clone(), public_key);
+        log!("Signing key set for group {}", group_id);
+    }
+
+    // Invitations: Retrieve the key that signs invitations for a group
+    pub fn get_group_signing_key(&self, group_id: String) -> Option<PublicKey> {
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        self.group_signing_keys.get(&group_id).cloned()
+    }
+
+    // Invitations: Redeem a signed invitation and join the group without the gating token
+    #[payable]
+    pub fn redeem_invitation(&mut self, invitation: Invitation, signature: String) {
+        self.assert_not_paused(Some(&invitation.group_id), PauseScope::Membership);
+        let group_id = invitation.group_id.clone();
+        assert!(self.groups.contains_key(&group_id), "Group not found");
+        let public_key = self.group_signing_keys.get(&group_id).expect("No signing key set for group");
+        let message = signed_message(INVITATION_TAG, &invitation);
+        assert!(verify_signature(public_key, &message, &signature), "Invalid invitation signature");
+        assert!(env::block_timestamp() < invitation.expires_at.0, "Invitation expired");
+        let caller = env::predecessor_account_id();
+        if let Some(invitee) = &invitation.invitee {
+            assert!(invitee == &caller, "Invitation was issued to another account");
+        }
+        assert!(
+            self.redeemed_invitations.insert((group_id.clone(), invitation.nonce.0)),
+            "Invitation already redeemed"
+        );
+        self.insert_group_member(&group_id, &caller);
+    }
+
+    // Sharing: Redeem a signed capability granting the caller time-limited access to one file.
+    // The caller pays for the grant's storage, since anyone can redeem a bearer capability.
+    #[payable]
+    pub fn redeem_share(&mut self, capability: ShareCapability, signature: String) {
+        self.assert_file_not_paused(&capability.trans_id, PauseScope::Keys);
+        let tx = self.transactions.get(&capability.trans_id).expect("Transaction not found");
+        let group_id = tx.group_id.clone();
+        let public_key = self.group_signing_keys.get(&group_id).expect("No signing key set for group");
+        let message = signed_message(SHARE_TAG, &capability);
+        assert!(verify_signature(public_key, &message, &signature), "Invalid share signature");
+        assert!(env::block_timestamp() < capability.expires_at.0, "Share expired");
+        assert!(!self.revoked_shares.contains(&(group_id, capability.nonce.0)), "Share revoked");
+        let caller = env::predecessor_account_id();
+        if let Some(grantee) = &capability.grantee {
+            assert!(grantee == &caller, "Share was issued to another account");
+        }
+        // Grantees outside the group never hold the group key, so a key share carries its own wrapped data key
+        assert!(
+            !capability.permissions.contains(&SharePermission::Key)
+                || capability.wrapped_key.as_ref().is_some_and(|wrapped_key| !wrapped_key.is_empty()),
+            "Key shares must carry the data key wrapped for the grantee"
+        );
+        let initial_storage = env::storage_usage();
+        let grant = ShareGrant {
+            expires_at: capability.expires_at,
+            permissions: capability.permissions,
+            nonce: capability.nonce,
+            wrapped_key: capability.wrapped_key,
+        };
+        self.share_grants.insert((capability.trans_id.clone(), caller.clone()), grant);
+        self.share_grants.flush();
+        self.charge_storage(&caller, initial_storage);
+        log!("File {} shared with {}", capability.trans_id, caller);
+    }
+
+    // Sharing: Revoke a capability before it expires, including grants already redeemed
+    #[payable]
+    pub fn revoke_share(&mut self, group_id: String, nonce: U64) {
+        self.assert_not_paused(Some(&group_id), PauseScope::Keys);
+        let group = self.groups.get(&group_id).expect("Group not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == group.owner || self.is_admin(&caller),
+            "Only group owner can revoke shares"
+        );
+        self.revoked_shares.insert((group_id.clone(), nonce.0));
+        log!("Share {} of group {} revoked", nonce.0, group_id);
+    }
+
+    pub fn is_share_revoked(&self, group_id: String, nonce: U64) -> bool {
+        self.revoked_shares.contains(&(group_id, nonce.0))
+    }
+
+    pub fn get_share(&self, trans_id: String, grantee: AccountId) -> Option<ShareGrant> {
+        self.share_grants.get(&(trans_id, grantee)).cloned()
+    }
+
+    // Sharing: Data key of a shared file, wrapped for the caller by the share issuer
+    pub fn get_shared_key(&self, trans_id: String) -> String {
+        let tx = self.transactions.get(&trans_id).expect("Transaction not found");
+        let caller = env::predecessor_account_id();
+        assert!(self.has_share(&trans_id, tx, &caller, SharePermission::Key), "No key share for this file");
+        assert!(!self.is_embargoed(tx, &caller), "File is under embargo");
+        self.share_grants.get(&(trans_id, caller)).and_then(|grant| grant.wrapped_key.clone()).unwrap()
+    }
+}
+
+// NEP-171: Transfers of membership tokens move the membership along with them
+#[near_bindgen]
+impl NonFungibleTokenCore for Contract {
+    #[payable]
+    fn nft_transfer(&mut self, receiver_id: AccountId, token_id: TokenId, approval_id: Option<u64>, memo: Option<String>) {
+        let group_id = self.transferable_token_group(&token_id);
+        let owner_id = self.membership_tokens.owner_by_id.get(&token_id).expect("Token not found");
+        self.membership_tokens.nft_transfer(receiver_id.clone(), token_id, approval_id, memo);
+        self.transfer_membership(&group_id, Some(&owner_id), Some(&receiver_id));
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let group_id = self.transferable_token_group(&token_id);
+        let owner_id = self.membership_tokens.owner_by_id.get(&token_id).expect("Token not found");
+        let result = self.membership_tokens.nft_transfer_call(receiver_id.clone(), token_id, approval_id, memo, msg);
+        self.transfer_membership(&group_id, Some(&owner_id), Some(&receiver_id));
+        result
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.membership_tokens.nft_token(token_id)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenResolver for Contract {
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        let transferred = self.membership_tokens.nft_resolve_transfer(
+            previous_owner_id.clone(),
+            receiver_id.clone(),
+            token_id.clone(),
+            approved_account_ids,
+        );
+        // A returned token takes its membership back to the previous owner
+        if !transferred {
+            if let Some(group_id) = self.token_groups.get(&token_id).cloned() {
+                self.transfer_membership(&group_id, Some(&receiver_id), Some(&previous_owner_id));
+            }
+        }
+        transferred
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenEnumeration for Contract {
+    fn nft_total_supply(&self) -> U128 {
+        self.membership_tokens.nft_total_supply()
+    }
+
+    fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        self.membership_tokens.nft_tokens(from_index, limit)
+    }
+
+    fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        self.membership_tokens.nft_supply_for_owner(account_id)
+    }
+
+    fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        self.membership_tokens.nft_tokens_for_owner(account_id, from_index, limit)
+    }
+}
+
+// NEP-177: Contract-level metadata of the membership tokens
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for Contract {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: MEMBERSHIP_TOKEN_NAME.to_string(),
+            symbol: MEMBERSHIP_TOKEN_SYMBOL.to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+impl Contract {
+    // Step 4: Check token ownership via cross-contract call to the NFT contract (1000fans.testnet by default),
+    // or to the group's NEP-141 contract when it is gated on a fungible token
+    fn check_token_and_add(&mut self, group_id: String, user_id: AccountId) -> Promise {
+        self.touch_account(&user_id);
+        if let Some(policy) = self.group_policies.get(&group_id).cloned() {
+            return self.check_policy_and_add(group_id, user_id, policy);
+        }
+        if let Some(gate) = self.ft_gates.get(&group_id) {
+            return ext_ft::ext(gate.token_contract.clone())
+                .with_static_gas(Gas::from_tgas(10))
+                .ft_balance_of(user_id.clone())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas::from_tgas(10))
+                        .add_group_member_ft_callback(group_id, user_id, gate.min_balance)
+                );
+        }
+        ext_nft::ext(self.nft_contract.clone())
+            .with_static_gas(Gas::from_tgas(10))
+            .nft_tokens_for_owner(user_id.clone(), None, Some(1))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .add_group_member_callback(group_id, user_id)
+            )
+    }
+
+    // Run the policy's cross-contract checks in parallel and combine them in one callback
+    fn check_policy_and_add(&self, group_id: String, user_id: AccountId, policy: AccessPolicy) -> Promise {
+        let mut checks = Vec::new();
+        policy.remote_checks(&mut checks);
+        let callback = Self::ext(env::current_account_id())
+            .with_static_gas(Gas::from_tgas(10))
+            .add_group_member_policy_callback(group_id, user_id.clone(), policy.clone());
+        let promises = checks.into_iter().map(|check| match check {
+            AccessPolicy::NftOwnership { contract_id } => ext_nft::ext(contract_id.clone())
+                .with_static_gas(Gas::from_tgas(10))
+                .nft_tokens_for_owner(user_id.clone(), None, None),
+            AccessPolicy::FtBalance { contract_id, .. } => ext_ft::ext(contract_id.clone())
+                .with_static_gas(Gas::from_tgas(10))
+                .ft_balance_of(user_id.clone()),
+            _ => unreachable!(),
+        });
+        match promises.reduce(|all, promise| all.and(promise)) {
+            Some(all) => all.then(callback),
+            None => callback,
+        }
+    }
+
+    // Take the protocol fee out of a sale and split the rest between payees by basis points
+    fn credit_sale(&mut self, amount: u128, payees: &[(AccountId, u16)]) {
+        let fee = amount * self.protocol_fee_bps as u128 / MAX_BPS as u128;
+        let mut remaining = amount - fee;
+        let net = remaining;
+        for (index, (payee, share_bps)) in payees.iter().enumerate() {
+            let share = if index == payees.len() - 1 { remaining } else { net * *share_bps as u128 / MAX_BPS as u128 };
+            remaining -= share;
+            self.credit_proceeds(payee, share);
+        }
+        let fee_recipient = self.fee_recipient.clone();
+        self.credit_proceeds(&fee_recipient, fee);
+    }
+
+    fn credit_proceeds(&mut self, account_id: &AccountId, amount: u128) {
+        if amount > 0 {
+            let balance = self.proceeds.get(account_id).copied().unwrap_or(0);
+            self.proceeds.insert(account_id.clone(), balance + amount);
+        }
+    }
+
+    // Reject uploads over the group's quotas and account for the new file
+    fn charge_quota(&mut self, group_id: &str, user_id: &AccountId, size_bytes: u64) {
+        let quota = self.storage_quotas.get(group_id).cloned().unwrap_or_default();
+        let mut usage = self.group_usage.get(group_id).cloned().unwrap_or_default();
+        let member_key = (group_id.to_string(), user_id.clone());
+        let mut member_usage = self.member_usage.get(&member_key).cloned().unwrap_or_default();
+        usage.total_bytes.0 += size_bytes;
+        usage.file_count.0 += 1;
+        member_usage.total_bytes.0 += size_bytes;
+        member_usage.file_count.0 += 1;
+        if let Some(max) = quota.max_total_bytes {
+            assert!(usage.total_bytes.0 <= max.0, "Group storage quota exceeded");
+        }
+        if let Some(max) = quota.max_file_count {
+            assert!(usage.file_count.0 <= max.0, "Group file count quota exceeded");
+        }
+        if let Some(max) = quota.max_bytes_per_member {
+            assert!(member_usage.total_bytes.0 <= max.0, "Member storage quota exceeded");
+        }
+        self.group_usage.insert(group_id.to_string(), usage);
+        self.member_usage.insert(member_key, member_usage);
+    }
+
+    // Take the storage added since `initial_storage` out of the attached deposit and refund the rest.
+    // Callers flush the collections they wrote first, so pending writes are measured.
+    fn charge_storage(&self, payer: &AccountId, initial_storage: u64) {
+        let cost = env::storage_byte_cost().as_yoctonear() * u128::from(env::storage_usage().saturating_sub(initial_storage));
+        let deposit = env::attached_deposit().as_yoctonear();
+        assert!(deposit >= cost, "Attach at least {} yoctoNEAR to cover the storage", cost);
+        if deposit > cost {
+            Promise::new(payer.clone()).transfer(NearToken::from_yoctonear(deposit - cost)).detach();
+        }
+    }
+
+    // A rotation that is open or still being aborted
+    fn rotation_in_progress(&self, group_id: &str) -> bool {
+        self.rotation_jobs
+            .get(group_id)
+            .is_some_and(|job| matches!(job.status, RotationStatus::InProgress | RotationStatus::Aborting))
+    }
+
+    fn rotation_aborting(&self, group_id: &str) -> bool {
+        self.rotation_jobs.get(group_id).is_some_and(|job| job.status == RotationStatus::Aborting)
+    }
+
+    // New epoch of the rotation accepting re-encrypted files, if any
+    fn open_rotation_epoch(&self, group_id: &str) -> Option<u64> {
+        self.rotation_jobs
+            .get(group_id)
+            .filter(|job| job.status == RotationStatus::InProgress)
+            .map(|job| job.new_epoch)
+    }
+
+    // Mark a file re-encrypted under the rotation's epoch, indexing it so an abort can restore it
+    fn settle_rotation_file(&mut self, key: &(String, String), epoch: u64) {
+        if self.rotated_files.insert(key.clone(), epoch) != Some(epoch) {
+            let job = self.rotation_jobs.get_mut(&key.0).unwrap();
+            self.rotation_changed_files.insert((key.0.clone(), job.changed_files), key.1.clone());
+            job.changed_files += 1;
+            job.pending_files = job.pending_files.saturating_sub(1);
+        }
+    }
+
+    fn current_key_epoch(&self, group_id: &str) -> u64 {
+        let epoch = self.key_epoch(group_id);
+        assert!(epoch > 0, "No group key set");
+        epoch
+    }
+
+    // Groups keyed before epochs were tracked count as epoch 1
+    fn key_epoch(&self, group_id: &str) -> u64 {
+        match self.key_epochs.get(group_id) {
+            Some(epoch) => *epoch,
+            None if self.groups.get(group_id).is_some_and(|group| group.group_key.is_some()) => 1,
+            None => 0,
+        }
+    }
+
+    // Epochs of aborted rotations are never reissued, so an epoch always names a single key
+    fn bump_key_epoch(&mut self, group_id: &str) {
+        let last_issued = self.rotation_jobs.get(group_id).map_or(0, |job| job.new_epoch);
+        let epoch = self.key_epoch(group_id).max(last_issued) + 1;
+        self.key_epochs.insert(group_id.to_string(), epoch);
+    }
+
+    fn settle_challenge(&mut self, challenge_id: u64, challenge: Challenge) {
+        let passed = challenge.status == ChallengeStatus::Passed;
+        // Providers removed since the challenge was issued have no standing to update
+        if let Some(provider) = self.pinning_providers.get_mut(&challenge.provider) {
+            if passed {
+                provider.challenges_passed += 1;
+            } else {
+                provider.challenges_failed += 1;
+            }
+        }
+        log!("Challenge {} {} by {}", challenge_id, if passed { "passed" } else { "failed" }, challenge.provider);
+        self.challenges.insert(challenge_id, challenge);
+    }
+
+    fn active_pins(&self, trans_id: &str) -> Vec<AccountId> {
+        self.file_pins
+            .get(trans_id)
+            .map(|pins| pins.iter().filter(|id| self.pinning_providers.contains_key(*id)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Upgrade delay in force, including a lowered delay whose notice period passed
+    fn upgrade_delay(&self) -> u64 {
+        match self.pending_upgrade_delay {
+            Some((delay_ns, effective_at)) if env::block_timestamp() >= effective_at => delay_ns,
+            _ => self.upgrade_delay_ns,
+        }
+    }
+
+    // The owner, or the contract itself executing a council proposal once a council is configured
+    fn is_admin(&self, account_id: &AccountId) -> bool {
+        match self.council {
+            Some(_) => account_id == &env::current_account_id(),
+            None => account_id == &self.owner || account_id == &env::current_account_id(),
+        }
+    }
+
+    // Pause flags bind agents and users. Admins are exempt so they can
+    // revoke leaked keys and members while the contract is paused.
+    fn assert_not_paused(&self, group_id: Option<&String>, scope: PauseScope) {
+        if self.is_admin(&env::predecessor_account_id()) {
+            return;
+        }
+        self.assert_scope_not_paused(group_id, scope);
+    }
+
+    // Pause check without the admin exemption, for callbacks the contract calls on itself
+    fn assert_scope_not_paused(&self, group_id: Option<&String>, scope: PauseScope) {
+        assert!(!self.pause_flags.contains(&(None, None)), "Contract is paused");
+        assert!(!self.pause_flags.contains(&(None, Some(scope))), "{:?} methods are paused", scope);
+        if let Some(group_id) = group_id {
+            assert!(!self.pause_flags.contains(&(Some(group_id.clone()), None)), "Group {} is paused", group_id);
+            assert!(
+                !self.pause_flags.contains(&(Some(group_id.clone()), Some(scope))),
+                "{:?} methods of group {} are paused",
+                scope,
+                group_id
+            );
+        }
+    }
+
+    fn assert_file_not_paused(&self, trans_id: &String, scope: PauseScope) {
+        let group_id = self.transactions.get(trans_id).map(|tx| tx.group_id.clone());
+        self.assert_not_paused(group_id.as_ref(), scope);
+    }
+
+    // Redeemed share of a file that is unexpired, unrevoked and grants the permission
+    fn has_share(&self, trans_id: &str, tx: &Transaction, account_id: &AccountId, permission: SharePermission) -> bool {
+        self.share_grants.get(&(trans_id.to_string(), account_id.clone())).is_some_and(|grant| {
+            env::block_timestamp() < grant.expires_at.0
+                && !self.revoked_shares.contains(&(tx.group_id.clone(), grant.nonce.0))
+                && grant.permissions.contains(&permission)
+        })
+    }
+
+    // Unreleased files are only visible to the group owner and agents
+    fn is_embargoed(&self, tx: &Transaction, account_id: &AccountId) -> bool {
+        let Some(release_at) = tx.release_at else {
+            return false;
+        };
+        if env::block_timestamp() >= release_at.0 || account_id == &self.owner || account_id.as_str().ends_with(".devbot.near") {
+            return false;
+        }
+        self.groups.get(&tx.group_id).is_none_or(|group| &group.owner != account_id)
+    }
+
+    // Link keys are bound to the CID and target key epoch they were wrapped for
+    fn store_link_key(&mut self, trans_id: &str, group_id: &str, wrapped_key: String) {
+        assert!(!wrapped_key.is_empty(), "Wrapped key cannot be empty");
+        let ipfs_hash = self.transactions.get(trans_id).unwrap().ipfs_hash.clone();
+        let epoch = self.current_key_epoch(group_id);
+        self.link_keys.insert((trans_id.to_string(), group_id.to_string()), (ipfs_hash, WrappedKey { wrapped_key, epoch }));
+    }
+
+    fn has_file_access(&self, trans_id: &str, tx: &Transaction, user_id: &AccountId) -> bool {
+        self.is_authorized(tx.group_id.clone(), user_id.clone())
+            || self.file_entitlements.contains(&(trans_id.to_string(), user_id.clone()))
+            || self
+                .file_links
+                .get(trans_id)
+                .is_some_and(|groups| groups.iter().any(|group_id| self.is_authorized(group_id.clone(), user_id.clone())))
+    }
+
+    fn touch_account(&mut self, account_id: &AccountId) {
+        if !self.account_first_seen.contains_key(account_id) {
+            self.account_first_seen.insert(account_id.clone(), env::block_timestamp());
+        }
+    }
+
+    // Membership or an active delegation from a member, ignoring parent groups
+    fn is_directly_authorized(&self, group_id: &str, user_id: &AccountId) -> bool {
+        if self.is_member(group_id, user_id) {
+            return true;
+        }
+        self.delegations.get(&(group_id.to_string(), user_id.clone())).is_some_and(|delegation| {
+            env::block_timestamp() < delegation.expires_at.0 && self.is_member(group_id, &delegation.delegator)
+        })
+    }
+
+    // Parent, grandparent and so on up to the root
+    fn ancestors(&self, group_id: &str) -> Vec<String> {
+        let mut ancestors = vec![];
+        let mut current = group_id.to_string();
+        while let Some(parent_id) = self.group_parents.get(&current) {
+            ancestors.push(parent_id.clone());
+            current = parent_id.clone();
+        }
+        ancestors
+    }
+
+    // Levels below a group, 0 for a group without children
+    fn subtree_height(&self, group_id: &str) -> usize {
+        self.group_children
+            .get(group_id)
+            .map(|children| children.iter().map(|child| 1 + self.subtree_height(child)).max().unwrap_or(0))
+            .unwrap_or(0)
+    }
+
+    // Membership admitted directly or through held membership tokens, ignoring delegations
+    fn is_member(&self, group_id: &str, user_id: &AccountId) -> bool {
+        self.group_members.get(group_id).expect("Group not found").contains(user_id)
+    }
+
+    fn remove_group_member(&mut self, group_id: &str, user_id: &AccountId) {
+        let members = self.group_members.get(group_id).expect("Group not found");
+        let mut members = members.to_vec();
+        if let Some(index) = members.iter().position(|x| x == user_id) {
+            members.remove(index);
+            self.group_members.insert(group_id.to_string(), members);
+            // Delegates lose access along with their delegator
+            let delegates = self.member_delegates.remove(&(group_id.to_string(), user_id.clone())).unwrap_or_default();
+            for delegate in delegates {
+                self.delegations.remove(&(group_id.to_string(), delegate));
+            }
+            log!("User {} revoked from group {}", user_id, group_id);
+        } else {
+            log!("User {} is not a member of group {}", user_id, group_id);
+        }
+    }
+
+    // Membership follows token ownership: holders stay members while they own any token of the group.
+    // Accounts also admitted directly (paid, invited, approved, NFT check) keep that membership.
+    fn transfer_membership(&mut self, group_id: &str, from: Option<&AccountId>, to: Option<&AccountId>) {
+        if let Some(from) = from {
+            let key = (group_id.to_string(), from.clone());
+            let count = self.member_token_counts.get(&key).copied().unwrap_or_default().saturating_sub(1);
+            if count > 0 {
+                self.member_token_counts.insert(key, count);
+            } else {
+                self.member_token_counts.remove(&key);
+                if self.token_only_members.remove(&key) {
+                    self.remove_group_member(group_id, from);
+                }
+            }
+        }
+        if let Some(to) = to {
+            let key = (group_id.to_string(), to.clone());
+            *self.member_token_counts.entry(key.clone()).or_default() += 1;
+            if !self.is_member(group_id, to) {
+                self.insert_group_member(group_id, to);
+                self.token_only_members.insert(key);
+            }
+        }
+    }
+
+    // Remove a membership token and the membership it granted, `caller` being the authorized burner
+    fn burn_token(&mut self, token_id: &TokenId, group_id: &str, owner_id: &AccountId, caller: &AccountId) {
+        self.membership_tokens.owner_by_id.remove(token_id);
+        if let Some(token_metadata_by_id) = &mut self.membership_tokens.token_metadata_by_id {
+            token_metadata_by_id.remove(token_id);
+        }
+        if let Some(tokens_per_owner) = &mut self.membership_tokens.tokens_per_owner {
+            if let Some(mut owner_tokens) = tokens_per_owner.get(owner_id) {
+                owner_tokens.remove(token_id);
+                if owner_tokens.is_empty() {
+                    tokens_per_owner.remove(owner_id);
+                } else {
+                    tokens_per_owner.insert(owner_id, &owner_tokens);
+                }
+            }
+        }
+        NftBurn {
+            owner_id,
+            token_ids: &[token_id],
+            authorized_id: (caller != owner_id).then_some(caller),
+            memo: None,
+        }
+        .emit();
+        self.token_groups.remove(token_id);
+        self.transfer_membership(group_id, Some(owner_id), None);
+        log!("Membership token {} of group {} burned", token_id, group_id);
+    }
+
+    // Group of a membership token about to change hands, which must not be soulbound
+    fn transferable_token_group(&self, token_id: &TokenId) -> String {
+        let group_id = self.token_groups.get(token_id).cloned().expect("Token not found");
+        self.assert_not_paused(Some(&group_id), PauseScope::Membership);
+        assert!(!self.soulbound_groups.contains(&group_id), "Membership tokens of group {} are soulbound", group_id);
+        group_id
+    }
+
+    // Admit a member directly, independent of any membership tokens they hold
+    fn insert_group_member(&mut self, group_id: &str, user_id: &AccountId) {
+        self.token_only_members.remove(&(group_id.to_string(), user_id.clone()));
+        let members = self.group_members.get(group_id).expect("Group not found");
+        let mut members = members.to_vec();
+        if !members.contains(user_id) {
+            members.push(user_id.clone());
+            self.group_members.insert(group_id.to_string(), members);
+            log!("User {} added to group {}", user_id, group_id);
+        } else {
+            log!("User {} is already a member of group {}", user_id, group_id);
+        }
+        // Admitting a user settles any pending membership request
+        let key = (group_id.to_string(), user_id.clone());
+        if let Some(request) = self.membership_requests.get(&key).cloned() {
+            if request.status == RequestStatus::Pending {
+                self.membership_requests.insert(key, MembershipRequest { status: RequestStatus::Approved, ..request });
+                self.remove_pending_request(group_id, user_id);
+            }
+        }
+    }
+
+    fn remove_pending_request(&mut self, group_id: &str, user_id: &AccountId) {
+        if let Some(pending) = self.pending_requests.get(group_id) {
+            let mut pending = pending.to_vec();
+            pending.retain(|x| x != user_id);
+            self.pending_requests.insert(group_id.to_string(), pending);
+        }
+    }
+
+    fn is_group_manager(&self, group_id: &str, account_id: &AccountId) -> bool {
+        self.group_managers.get(group_id).is_some_and(|managers| managers.contains(account_id))
+    }
+}
+
+// Group id carried in the `extra` metadata of a 1000fans token
+fn token_group_id(token: &Token) -> Option<String> {
+    let extra = token.metadata.as_ref()?.extra.as_ref()?;
+    let extra_json: serde_json::Value = serde_json::from_str(extra).ok()?;
+    extra_json["group_id"].as_str().map(str::to_string)
+}
+
+fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.to_le_bytes());
+}
+
+// Log a NEP-297 event for indexers
+fn emit_event(event: &str, data: serde_json::Value) {
+    let event = serde_json::json!({ "standard": EVENT_STANDARD, "version": EVENT_VERSION, "event": event, "data": [data] });
+    log!("EVENT_JSON:{}", event);
+}
+
+// Read the result of a cross-contract call, or None if the call failed
+#[allow(deprecated)]
+fn promise_result_value(index: u64) -> Option<Vec<u8>> {
+    match env::promise_result(index) {
+        PromiseResult::Successful(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Walk a chunk's Merkle path up to the recorded root
+fn verify_chunk_proof(chunk_root: &ChunkRoot, index: u32, proof: &ChunkProof) -> bool {
+    let depth = chunk_root.chunk_count.next_power_of_two().trailing_zeros() as usize;
+    if proof.siblings.len() != depth {
+        return false;
+    }
+    let Ok(chunk) = hex::decode(&proof.chunk) else {
+        return false;
+    };
+    let mut node = env::sha256(&chunk);
+    let mut position = index;
+    for sibling in &proof.siblings {
+        let Ok(sibling) = hex::decode(sibling) else {
+            return false;
+        };
+        node = if position.is_multiple_of(2) {
+            env::sha256([node, sibling].concat())
+        } else {
+            env::sha256([sibling, node].concat())
+        };
+        position /= 2;
+    }
+    hex::encode(node) == chunk_root.merkle_root
+}
+
+// Bind a payload signed with a group signing key to this contract and to its type, so the
+// signature cannot be replayed on another deployment or accepted as another kind of message
+fn signed_message<T: BorshSerialize>(tag: &str, payload: &T) -> Vec<u8> {
+    borsh::to_vec(&(env::current_account_id(), tag, payload)).expect("Failed to serialize signed message")
+}
+
+// Check a hex-encoded ed25519 signature against an ed25519 public key
+fn verify_signature(public_key: &PublicKey, message: &[u8], signature: &str) -> bool {
+    if public_key.curve_type() != CurveType::ED25519 {
+        return false;
+    }
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(signature) = <[u8; 64]>::try_from(signature.as_slice()) else {
+        return false;
+    };
+    let Ok(key) = <[u8; 32]>::try_from(&public_key.as_bytes()[1..]) else {
+        return false;
+    };
+    env::ed25519_verify(&signature, message, &key)
+}
 
 // External interface for cross-contract calls
 #[near_sdk::ext_contract(ext_nft)]
@@ -362,571 +3595,3118 @@ pub trait ExtNft {
     fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token>;
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::test_utils::{VMContextBuilder, get_logs};
-    use near_sdk::{testing_env, NearToken};
-    use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
-    use serde_json::json;
+#[near_sdk::ext_contract(ext_ft)]
+pub trait ExtFt {
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{VMContextBuilder, get_logs};
+    use near_sdk::{testing_env, NearToken};
+    use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+    use serde_json::json;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn setup_context(predecessor: AccountId) -> VMContextBuilder {
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(predecessor)
+            .current_account_id("devbot.near".parse().unwrap())
+            .account_balance(NearToken::from_yoctonear(100_000_000_000_000_000_000_000_000))
+            .attached_deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000));
+        context
+    }
+
+    fn create_mock_token(user_id: AccountId, group_id: &str) -> Token {
+        Token {
+            token_id: "fan000".to_string(),
+            owner_id: user_id,
+            metadata: Some(TokenMetadata {
+                title: Some("1000fans Access Token".into()),
+                description: Some("Grants access".into()),
+                media: None,
+                media_hash: None,
+                copies: Some(1u64),
+                issued_at: Some("0".into()),
+                expires_at: None,
+                starts_at: None,
+                updated_at: None,
+                extra: Some(serde_json::to_string(&json!({ "group_id": group_id })).unwrap()),
+                reference: None,
+                reference_hash: None,
+            }),
+            approved_account_ids: None,
+        }
+    }
+
+    fn setup_callback_context(results: Vec<PromiseResult>) {
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            results
+        );
+    }
+
+    // Register the test storage agent key and sign an upload claim with it
+    fn attest(contract: &mut Contract, group_id: &str, user_id: &str, file_hash: &str, ipfs_hash: &str, size_bytes: u64) -> UploadAttestation {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let public_key = signing_public_key(&key);
+        contract.attestation_keys.insert(public_key.clone(), "storage-agent.devbot.near".parse().unwrap());
+        let user_id: AccountId = user_id.parse().unwrap();
+        let claim = borsh::to_vec(&(&group_id.to_string(), &user_id, &file_hash.to_string(), &ipfs_hash.to_string(), size_bytes)).unwrap();
+        UploadAttestation { public_key, signature: hex::encode(key.sign(&claim).to_bytes()) }
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    fn signing_public_key(key: &SigningKey) -> PublicKey {
+        PublicKey::from_parts(CurveType::ED25519, key.verifying_key().to_bytes().to_vec()).unwrap()
+    }
+
+    fn sign_invitation(key: &SigningKey, invitation: &Invitation) -> String {
+        hex::encode(key.sign(&signed_message(INVITATION_TAG, invitation)).to_bytes())
+    }
+
+    fn sign_share(key: &SigningKey, capability: &ShareCapability) -> String {
+        hex::encode(key.sign(&signed_message(SHARE_TAG, capability)).to_bytes())
+    }
+
+    #[test]
+    fn test_register_group() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        assert!(contract.groups.contains_key(&"group1".to_string()));
+        assert_eq!(get_logs(), vec!["Group group1 registered by auth-agent.devbot.near"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only contract owner or devbot agents can register a group")]
+    fn test_register_group_unauthorized() {
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.register_group("group1".to_string());
+    }
+
+    #[test]
+    fn test_add_group_member() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+
+        // Trigger cross-contract call
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+
+        // Simulate successful promise result
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert_eq!(get_logs().last().unwrap(), "User user.near added to group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "User does not own a 1000fans token")]
+    fn test_add_group_member_no_nft() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+
+        // Trigger cross-contract call
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+
+        // Simulate empty token list
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token group_id does not match group")]
+    fn test_add_group_member_wrong_group_id() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+
+        // Trigger cross-contract call
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+
+        // Simulate token with wrong group_id
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group2")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_set_nft_contract() {
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        assert_eq!(contract.get_nft_contract().as_str(), "1000fans.testnet");
+        contract.set_nft_contract("mock-nft.testnet".parse().unwrap());
+        assert_eq!(contract.get_nft_contract().as_str(), "mock-nft.testnet");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only contract owner can set the NFT contract")]
+    fn test_set_nft_contract_unauthorized() {
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_nft_contract("mock-nft.testnet".parse().unwrap());
+    }
+
+    #[test]
+    fn test_revoke_group_member() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Revoke member
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.revoke_group_member("group1".to_string(), "user.near".parse().unwrap());
+        assert!(!contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert_eq!(get_logs().last().unwrap(), "User user.near revoked from group group1");
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(!contract.is_authorized("group1".to_string(), "other.near".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_record_transaction() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        assert_eq!(get_logs().last().unwrap(), &format!("Transaction recorded: {}", trans_id));
+        let tx = contract.get_transaction(trans_id.clone()).unwrap();
+        assert_eq!(tx.group_id, "group1");
+        assert_eq!(tx.ipfs_hash, "QmTest");
+    }
+
+    #[test]
+    fn test_store_group_key() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        let group = contract.groups.get(&"group1".to_string()).expect("Group not found");
+        assert_eq!(group.group_key, Some("symmetric_key_123".to_string()));
+        assert_eq!(get_logs().last().unwrap(), "Group key stored for group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Group key already set, use rotate_group_key")]
+    fn test_store_group_key_twice() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_456".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or devbot agents can store group key")]
+    fn test_store_group_key_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Group key cannot be empty")]
+    fn test_store_group_key_empty() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "".to_string());
+    }
+
+    #[test]
+    fn test_get_group_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Get key as the auth-agent
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let key = contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+        assert_eq!(key, "symmetric_key_123");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or devbot agents can retrieve the group key, members use request_group_key")]
+    fn test_get_group_key_as_member() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Members cannot skip the audited request_group_key
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "User not authorized")]
+    fn test_get_group_key_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or devbot agents can retrieve the group key")]
+    fn test_get_group_key_wrong_caller() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Wrong caller
+        let context = setup_context("other.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_rotate_group_key() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        contract.rotate_group_key("group1".to_string(), "new_symmetric_key_456".to_string());
+        let group = contract.groups.get(&"group1".to_string()).expect("Group not found");
+        assert_eq!(group.group_key, Some("new_symmetric_key_456".to_string()));
+        assert_eq!(get_logs().last().unwrap(), "Group key rotated for group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or devbot agents can rotate group key")]
+    fn test_rotate_group_key_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.rotate_group_key("group1".to_string(), "new_symmetric_key_456".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "New group key cannot be empty")]
+    fn test_rotate_group_key_empty() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        contract.rotate_group_key("group1".to_string(), "".to_string());
+    }
+
+    #[test]
+    fn test_get_transactions_for_group() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let _trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Get transactions
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        let transactions = contract.get_transactions_for_group("group1".to_string());
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].ipfs_hash, "QmTest");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group members, owner, or devbot agents can view transactions")]
+    fn test_get_transactions_for_group_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_transactions_for_group("group1".to_string());
+    }
+
+    #[test]
+    fn test_update_group_files() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Update files
+        contract.update_group_files("group1".to_string(), vec![(trans_id.clone(), "QmNewHash".to_string())]);
+        let tx = contract.get_transaction(trans_id).unwrap();
+        assert_eq!(tx.ipfs_hash, "QmNewHash");
+        assert_eq!(get_logs().last().unwrap(), "IPFS hashes updated for 1 files of group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "does not belong to group group2")]
+    fn test_update_group_files_wrong_group() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.register_group("group2".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Update through another group
+        contract.update_group_files("group2".to_string(), vec![(trans_id, "QmNewHash".to_string())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or devbot agents can update group files")]
+    fn test_update_group_files_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.update_group_files("group1".to_string(), vec![("trans".to_string(), "QmNewHash".to_string())]);
+    }
+
+    #[test]
+    fn test_store_file_metadata() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Store metadata
+        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
+        let metadata = contract.get_file_metadata(trans_id.clone()).unwrap();
+        assert_eq!(metadata, "file_size:1MB");
+        assert_eq!(get_logs().last().unwrap(), &format!("Metadata stored for transaction {}", trans_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only devbot agents can store file metadata")]
+    fn test_store_file_metadata_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Unauthorized caller
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.store_file_metadata(trans_id, "file_size:1MB".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Metadata cannot be empty")]
+    fn test_store_file_metadata_empty() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Empty metadata
+        contract.store_file_metadata(trans_id, "".to_string());
+    }
+
+    #[test]
+    fn test_get_file_metadata() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Store and get metadata
+        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        let metadata = contract.get_file_metadata(trans_id).unwrap();
+        assert_eq!(metadata, "file_size:1MB");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group members, owner, or devbot agents can view metadata")]
+    fn test_get_file_metadata_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // Store metadata
+        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
+        // Unauthorized caller
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_file_metadata(trans_id);
+    }
+
+    #[test]
+    fn test_redeem_invitation() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: Some("press.near".parse().unwrap()),
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        let signature = sign_invitation(&key, &invitation);
+        // Redeem as the invitee
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_invitation(invitation, signature);
+        assert!(contract.is_authorized("group1".to_string(), "press.near".parse().unwrap()));
+        assert_eq!(get_logs().last().unwrap(), "User press.near added to group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid invitation signature")]
+    fn test_redeem_invitation_bad_signature() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&signing_key()));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: None,
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        // Signed by a key that is not registered on the group
+        let signature = sign_invitation(&SigningKey::from_bytes(&[9u8; 32]), &invitation);
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_invitation(invitation, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invitation expired")]
+    fn test_redeem_invitation_expired() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: None,
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        let signature = sign_invitation(&key, &invitation);
+        let mut context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.block_timestamp(2_000).build());
+        contract.redeem_invitation(invitation, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invitation already redeemed")]
+    fn test_redeem_invitation_twice() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: None,
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        let signature = sign_invitation(&key, &invitation);
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_invitation(invitation.clone(), signature.clone());
+        // Open invitations are still single-use
+        let context = setup_context("other.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_invitation(invitation, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invitation was issued to another account")]
+    fn test_redeem_invitation_wrong_invitee() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: Some("press.near".parse().unwrap()),
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        let signature = sign_invitation(&key, &invitation);
+        let context = setup_context("other.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_invitation(invitation, signature);
+    }
+
+    #[test]
+    fn test_approve_membership_request() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Request membership
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), Some("Big fan".to_string()));
+        let request = contract.get_membership_request("group1".to_string(), "user.near".parse().unwrap()).unwrap();
+        assert_eq!(request.status, RequestStatus::Pending);
+        // Approve through the token check
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let pending = contract.get_pending_requests("group1".to_string(), None, None);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].1.message, Some("Big fan".to_string()));
+        let _ = contract.approve_membership_request("group1".to_string(), "user.near".parse().unwrap(), Some("Welcome".to_string()));
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        let request = contract.get_membership_request("group1".to_string(), "user.near".parse().unwrap()).unwrap();
+        assert_eq!(request.status, RequestStatus::Approved);
+        assert_eq!(request.reason, Some("Welcome".to_string()));
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        assert!(contract.get_pending_requests("group1".to_string(), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_deny_membership_request() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.add_group_manager("group1".to_string(), "manager.near".parse().unwrap());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), None);
+        // Deny as a manager
+        let context = setup_context("manager.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.deny_membership_request("group1".to_string(), "user.near".parse().unwrap(), Some("Group is full".to_string()));
+        let request = contract.get_membership_request("group1".to_string(), "user.near".parse().unwrap()).unwrap();
+        assert_eq!(request.status, RequestStatus::Denied);
+        assert_eq!(request.reason, Some("Group is full".to_string()));
+        assert!(contract.get_pending_requests("group1".to_string(), None, None).is_empty());
+        assert_eq!(get_logs().last().unwrap(), "Membership request of user.near for group group1 denied by manager.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Membership request already pending")]
+    fn test_request_membership_twice() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), None);
+        contract.request_membership("group1".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach at least")]
+    fn test_request_membership_without_deposit() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let mut context = setup_context("user.near".parse().unwrap());
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), Some("Big fan".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Request message exceeds 280 characters")]
+    fn test_request_membership_message_too_long() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), Some("a".repeat(281)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner, managers, or devbot agents can view membership requests")]
+    fn test_get_pending_requests_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_pending_requests("group1".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_add_group_member_ft_gate() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_ft_gate("group1".to_string(), "token.near".parse().unwrap(), U128(100));
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        // Simulate ft_balance_of returning enough tokens
+        setup_callback_context(vec![PromiseResult::Successful(serde_json::to_vec(&U128(150)).unwrap())]);
+        contract.add_group_member_ft_callback("group1".to_string(), "user.near".parse().unwrap(), U128(100));
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "User does not hold enough tokens")]
+    fn test_add_group_member_ft_gate_insufficient_balance() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_ft_gate("group1".to_string(), "token.near".parse().unwrap(), U128(100));
+        setup_callback_context(vec![PromiseResult::Successful(serde_json::to_vec(&U128(99)).unwrap())]);
+        contract.add_group_member_ft_callback("group1".to_string(), "user.near".parse().unwrap(), U128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner can configure token gating")]
+    fn test_set_ft_gate_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_ft_gate("group1".to_string(), "token.near".parse().unwrap(), U128(100));
+    }
+
+    #[test]
+    fn test_group_policy_local_conditions() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_group_policy("group1".to_string(), AccessPolicy::AllOf(vec![
+            AccessPolicy::Allowlist { accounts: vec!["user.near".parse().unwrap()] },
+            AccessPolicy::MinAccountAge { min_age: U64(1_000) },
+        ]));
+        // First seen when requesting membership
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_membership("group1".to_string(), None);
+        // Admitted once the account is old enough
+        let mut context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.block_timestamp(5_000).build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let policy = contract.get_group_policy("group1".to_string()).unwrap();
+        let mut context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.block_timestamp(5_000).build());
+        contract.add_group_member_policy_callback("group1".to_string(), "user.near".parse().unwrap(), policy);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_group_policy_any_of() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let policy = AccessPolicy::AnyOf(vec![
+            AccessPolicy::NftOwnership { contract_id: "1000fans.testnet".parse().unwrap() },
+            AccessPolicy::FtBalance { contract_id: "token.near".parse().unwrap(), min_balance: U128(100) },
+        ]);
+        contract.set_group_policy("group1".to_string(), policy.clone());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        // NFT check fails, FT balance is enough
+        setup_callback_context(vec![
+            PromiseResult::Failed,
+            PromiseResult::Successful(serde_json::to_vec(&U128(100)).unwrap()),
+        ]);
+        contract.add_group_member_policy_callback("group1".to_string(), "user.near".parse().unwrap(), policy);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "User does not satisfy the group access policy")]
+    fn test_group_policy_all_of_rejected() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let policy = AccessPolicy::AllOf(vec![
+            AccessPolicy::NftOwnership { contract_id: "1000fans.testnet".parse().unwrap() },
+            AccessPolicy::FtBalance { contract_id: "token.near".parse().unwrap(), min_balance: U128(100) },
+        ]);
+        contract.set_group_policy("group1".to_string(), policy.clone());
+        // Token belongs to another group
+        setup_callback_context(vec![
+            PromiseResult::Successful(serde_json::to_vec(&vec![create_mock_token("user.near".parse().unwrap(), "group2")]).unwrap()),
+            PromiseResult::Successful(serde_json::to_vec(&U128(100)).unwrap()),
+        ]);
+        contract.add_group_member_policy_callback("group1".to_string(), "user.near".parse().unwrap(), policy);
+    }
+
+    #[test]
+    fn test_buy_membership() {
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_protocol_fee(1_000);
+        let context = setup_context("owner.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.register_group("group1".to_string());
+        contract.set_membership_price("group1".to_string(), Some(U128(1_000)));
+        // Pay more than the price
+        let mut context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_500)).build());
+        contract.buy_membership("group1".to_string());
+        assert!(contract.is_authorized("group1".to_string(), "fan.near".parse().unwrap()));
+        assert_eq!(contract.get_proceeds("owner.devbot.near".parse().unwrap()), U128(900));
+        assert_eq!(contract.get_proceeds("storage-agent.devbot.near".parse().unwrap()), U128(100));
+        // Group owner withdraws
+        let context = setup_context("owner.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let _ = contract.withdraw_proceeds();
+        assert_eq!(contract.get_proceeds("owner.devbot.near".parse().unwrap()), U128(0));
+    }
+
+    #[test]
+    fn test_withdraw_proceeds_failed_transfer() {
+        let context = setup_context("owner.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_membership_price("group1".to_string(), Some(U128(1_000)));
+        let mut context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_000)).build());
+        contract.buy_membership("group1".to_string());
+        let context = setup_context("owner.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let _ = contract.withdraw_proceeds();
+        // The transfer fails, e.g. the account was deleted
+        testing_env!(
+            setup_context("devbot.near".parse().unwrap()).build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.withdraw_proceeds_callback("owner.devbot.near".parse().unwrap(), U128(1_000));
+        assert_eq!(contract.get_proceeds("owner.devbot.near".parse().unwrap()), U128(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is below the membership price")]
+    fn test_buy_membership_underpaid() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_membership_price("group1".to_string(), Some(U128(1_000)));
+        let mut context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(999)).build());
+        contract.buy_membership("group1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Group membership is not for sale")]
+    fn test_buy_membership_not_for_sale() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.buy_membership("group1".to_string());
+    }
+
+    #[test]
+    fn test_purchase_file() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction and put it on sale
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
+        contract.set_file_price(trans_id.clone(), Some(U128(1_000)));
+        contract.set_file_sale_split("group1".to_string(), 7_000);
+        // Non-member buys the file
+        let mut context = setup_context("buyer.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_000)).build());
+        contract.purchase_file(trans_id.clone());
+        assert!(contract.can_access_file(trans_id.clone(), "buyer.near".parse().unwrap()));
+        assert!(!contract.is_authorized("group1".to_string(), "buyer.near".parse().unwrap()));
+        assert_eq!(contract.get_file_metadata(trans_id).unwrap(), "file_size:1MB");
+        assert_eq!(contract.get_proceeds("user.near".parse().unwrap()), U128(700));
+        assert_eq!(contract.get_proceeds("auth-agent.devbot.near".parse().unwrap()), U128(300));
+    }
+
+    #[test]
+    #[should_panic(expected = "File is not for sale")]
+    fn test_purchase_file_not_for_sale() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        let context = setup_context("buyer.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.purchase_file(trans_id);
+    }
+
+    #[test]
+    fn test_storage_quota() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_storage_quota("group1".to_string(), StorageQuota {
+            max_total_bytes: Some(U64(2_000)),
+            max_file_count: Some(U64(10)),
+            max_bytes_per_member: None,
+        });
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_500);
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_500),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        let report = contract.get_storage_usage("group1".to_string());
+        assert_eq!(report.usage.total_bytes, U64(1_500));
+        assert_eq!(report.usage.file_count, U64(1));
+        assert_eq!(report.quota.max_total_bytes, Some(U64(2_000)));
+        let member = contract.get_member_storage_usage("group1".to_string(), "user.near".parse().unwrap());
+        assert_eq!(member.total_bytes, U64(1_500));
+    }
+
+    #[test]
+    #[should_panic(expected = "Member storage quota exceeded")]
+    fn test_storage_quota_exceeded() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.set_storage_quota("group1".to_string(), StorageQuota {
+            max_total_bytes: None,
+            max_file_count: None,
+            max_bytes_per_member: Some(U64(1_000)),
+        });
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Second upload goes over the member cap
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 600);
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(600),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        let attestation = attest(&mut contract, "group1", "user.near", "def456", "QmTest2", 600);
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "def456".to_string(),
+            "QmTest2".to_string(),
+            U64(600),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_migrate_from_v0() {
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        {
+            let mut old = ContractV0 {
+                owner: "devbot.near".parse().unwrap(),
+                transactions: IterableMap::new(b"t"),
+                groups: LookupMap::new(b"g"),
+                group_members: LookupMap::new(b"m"),
+                file_metadata: LookupMap::new(b"f"),
+            };
+            old.groups.insert("group1".to_string(), Group { owner: "devbot.near".parse().unwrap(), group_key: None });
+            old.group_members.insert("group1".to_string(), vec!["user.near".parse().unwrap()]);
+            old.transactions.insert("tx1".to_string(), TransactionV0 {
+                group_id: "group1".to_string(),
+                user_id: "user.near".to_string(),
+                file_hash: "abc123".to_string(),
+                ipfs_hash: "QmTest".to_string(),
+            });
+            env::state_write(&old);
+        }
+        {
+            let mut contract = Contract::migrate();
+            assert!(contract.get_transaction("tx1".to_string()).is_none());
+            assert_eq!(contract.migrate_transactions(None), U64(0));
+            env::state_write(&contract);
+        }
+        let contract: Contract = env::state_read().unwrap();
+        assert!(contract.legacy_transactions.is_none());
+        let tx = contract.get_transaction("tx1".to_string()).unwrap();
+        assert_eq!(tx.ipfs_hash, "QmTest");
+        assert_eq!(tx.size_bytes, U64(0));
+        assert_eq!(tx.recorded_by, None);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert_eq!(contract.get_storage_usage("group1".to_string()).usage.file_count, U64(1));
+    }
+
+    #[test]
+    fn test_record_transaction_details() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let mut context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.block_timestamp(42_000).block_height(7).build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            Some("track.mp3".to_string()),
+            Some("audio/mpeg".to_string()),
+            attestation,
+            None,
+            None,
+        );
+        let tx = contract.get_transaction(trans_id).unwrap();
+        assert_eq!(tx.created_at, U64(42_000));
+        assert_eq!(tx.block_height, U64(7));
+        assert_eq!(tx.recorded_by, Some("storage-agent.devbot.near".parse().unwrap()));
+        assert_eq!(tx.size_bytes, U64(1_000));
+        assert_eq!(tx.file_name, Some("track.mp3".to_string()));
+        assert_eq!(tx.mime_type, Some("audio/mpeg".to_string()));
+    }
+
+    #[test]
+    fn test_record_transaction_attestation() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        contract.register_attestation_key(signing_public_key(&key), "storage-agent.devbot.near".parse().unwrap());
+        assert_eq!(contract.get_attestation_keys().len(), 1);
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation.clone(),
+            Some("wrapped-e1".to_string()),
+            None,
+        );
+        let tx = contract.get_transaction(trans_id).unwrap();
+        assert_eq!(tx.attestation, Some(attestation));
+        assert_eq!(tx.wrapped_key, Some(WrappedKey { wrapped_key: "wrapped-e1".to_string(), epoch: 1 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid upload attestation")]
+    fn test_record_transaction_bad_attestation() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Attestation covers a different CID
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmOther", 1_000);
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Attestation key not registered")]
+    fn test_record_transaction_revoked_attestation_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Key is revoked before the upload is recorded
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        contract.revoke_attestation_key(attestation.public_key.clone());
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_replication_tracking() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.register_pinning_provider("pinata.near".parse().unwrap(), "https://api.pinata.cloud".to_string());
+        contract.register_pinning_provider("filebase.near".parse().unwrap(), "https://api.filebase.io".to_string());
+        contract.set_replication_target("group1".to_string(), 2);
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        // One provider pins it
+        let context = setup_context("pinata.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.report_pin(trans_id.clone());
+        assert_eq!(contract.get_under_replicated_files("group1".to_string(), None, None), vec![(trans_id.clone(), 1)]);
+        // Second provider pins it
+        let context = setup_context("filebase.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.report_pin(trans_id.clone());
+        assert!(contract.get_under_replicated_files("group1".to_string(), None, None).is_empty());
+        // Unpin drops it back under target
+        contract.report_unpin(trans_id.clone());
+        assert_eq!(contract.get_file_pins(trans_id.clone()), vec!["pinata.near".parse::<AccountId>().unwrap()]);
+        assert_eq!(contract.get_replication_count(trans_id.clone()), 1);
+        assert_eq!(contract.get_under_replicated_files("group1".to_string(), None, None), vec![(trans_id, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only registered pinning providers can report pins")]
+    fn test_report_pin_unregistered() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.report_pin(trans_id);
+    }
 
-    fn setup_context(predecessor: AccountId) -> VMContextBuilder {
-        let mut context = VMContextBuilder::new();
-        context
-            .predecessor_account_id(predecessor)
-            .current_account_id("devbot.near".parse().unwrap())
-            .account_balance(NearToken::from_yoctonear(100_000_000_000_000_000_000_000_000))
-            .attached_deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000));
-        context
+    // Build Merkle levels over sha256 chunk hashes, pairing the last odd node with itself
+    fn merkle_levels(chunks: &[Vec<u8>]) -> Vec<Vec<Vec<u8>>> {
+        let mut levels = vec![chunks.iter().map(env::sha256).collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap();
+            let next = level
+                .chunks(2)
+                .map(|pair| env::sha256([pair[0].clone(), pair.get(1).unwrap_or(&pair[0]).clone()].concat()))
+                .collect();
+            levels.push(next);
+        }
+        levels
     }
 
-    fn create_mock_token(user_id: AccountId, group_id: &str) -> Token {
-        Token {
-            token_id: "fan000".to_string(),
-            owner_id: user_id,
-            metadata: Some(TokenMetadata {
-                title: Some("1000fans Access Token".into()),
-                description: Some("Grants access".into()),
-                media: None,
-                media_hash: None,
-                copies: Some(1u64),
-                issued_at: Some("0".into()),
-                expires_at: None,
-                starts_at: None,
-                updated_at: None,
-                extra: Some(serde_json::to_string(&json!({ "group_id": group_id })).unwrap()),
-                reference: None,
-                reference_hash: None,
-            }),
-            approved_account_ids: None,
+    fn chunk_proof(chunks: &[Vec<u8>], index: u32) -> ChunkProof {
+        let levels = merkle_levels(chunks);
+        let mut position = index as usize;
+        let mut siblings = vec![];
+        for level in &levels[..levels.len() - 1] {
+            let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+            siblings.push(hex::encode(sibling));
+            position /= 2;
         }
+        ChunkProof { chunk: hex::encode(&chunks[index as usize]), siblings }
+    }
+
+    // Record a pinned file with a chunk root, returns its trans_id
+    fn setup_pinned_file(chunks: &[Vec<u8>]) -> (Contract, String) {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.register_pinning_provider("pinata.near".parse().unwrap(), "https://api.pinata.cloud".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transaction
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let attestation = attest(&mut contract, "group1", "user.near", "abc123", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "abc123".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+        let levels = merkle_levels(chunks);
+        contract.set_chunk_root(trans_id.clone(), hex::encode(&levels.last().unwrap()[0]), chunks.len() as u32);
+        let context = setup_context("pinata.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.report_pin(trans_id.clone());
+        (contract, trans_id)
+    }
+
+    #[test]
+    fn test_retrievability_challenge() {
+        let chunks: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 64]).collect();
+        let (mut contract, trans_id) = setup_pinned_file(&chunks);
+        // Issue challenge
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let challenge_id = contract.issue_challenge(trans_id, "pinata.near".parse().unwrap());
+        let challenge = contract.get_challenge(challenge_id).unwrap();
+        assert_eq!(challenge.chunk_indices.len(), 3);
+        assert!(challenge.chunk_indices.iter().all(|index| *index < 5));
+        // Provider answers with valid proofs
+        let context = setup_context("pinata.near".parse().unwrap());
+        testing_env!(context.build());
+        let proofs = challenge.chunk_indices.iter().map(|index| chunk_proof(&chunks, *index)).collect();
+        assert!(contract.respond_to_challenge(challenge_id, proofs));
+        assert_eq!(contract.get_challenge(challenge_id).unwrap().status, ChallengeStatus::Passed);
+        assert_eq!(contract.get_provider_standing("pinata.near".parse().unwrap()), 10_000);
+    }
+
+    #[test]
+    fn test_retrievability_challenge_failures() {
+        let chunks: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 64]).collect();
+        let (mut contract, trans_id) = setup_pinned_file(&chunks);
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let first = contract.issue_challenge(trans_id.clone(), "pinata.near".parse().unwrap());
+        let second = contract.issue_challenge(trans_id, "pinata.near".parse().unwrap());
+        // Wrong chunk data fails the first challenge
+        let context = setup_context("pinata.near".parse().unwrap());
+        testing_env!(context.build());
+        let challenge = contract.get_challenge(first).unwrap();
+        let proofs = challenge
+            .chunk_indices
+            .iter()
+            .map(|index| ChunkProof { chunk: hex::encode([9u8; 64]), ..chunk_proof(&chunks, *index) })
+            .collect();
+        assert!(!contract.respond_to_challenge(first, proofs));
+        assert_eq!(contract.get_challenge(first).unwrap().status, ChallengeStatus::Failed);
+        // Second challenge goes unanswered past its deadline
+        let mut context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.block_timestamp(CHALLENGE_WINDOW_NS + 1).build());
+        contract.expire_challenge(second);
+        assert_eq!(contract.get_challenge(second).unwrap().status, ChallengeStatus::Failed);
+        assert_eq!(contract.get_provider_standing("pinata.near".parse().unwrap()), 0);
+    }
+
+    #[test]
+    fn test_request_group_key_audit() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        // Add member
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Request key before and after a rotation
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.block_timestamp(100).build());
+        assert_eq!(contract.request_group_key("group1".to_string()), "symmetric_key_123");
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.block_timestamp(200).build());
+        assert_eq!(contract.request_group_key("group1".to_string()), "symmetric_key_456");
+        // Owner pages through the log
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        assert_eq!(contract.get_key_access_count("group1".to_string()), 2);
+        let log = contract.get_key_access_log("group1".to_string(), Some(1), Some(10));
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].account_id, "user.near".parse::<AccountId>().unwrap());
+        assert_eq!(log[0].timestamp, U64(200));
+        assert_eq!(log[0].key_epoch, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner or members can request the group key")]
+    fn test_request_group_key_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.request_group_key("group1".to_string());
+    }
+
+    // Register group1 with two recorded files, returns their trans_ids
+    fn setup_group_files(contract: &mut Contract) -> Vec<String> {
+        contract.register_group("group1".to_string());
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        // Add member
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let _ = contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        // Record transactions
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        ["abc123", "def456"]
+            .iter()
+            .map(|file_hash| {
+                let attestation = attest(contract, "group1", "user.near", file_hash, "QmTest", 1_000);
+                contract.record_transaction(
+                    "group1".to_string(),
+                    "user.near".parse().unwrap(),
+                    file_hash.to_string(),
+                    "QmTest".to_string(),
+                    U64(1_000),
+                    None,
+                    None,
+                    attestation,
+                    None,
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_key_rotation_job() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        let job = contract.get_rotation_job("group1".to_string()).unwrap();
+        assert_eq!((job.previous_epoch, job.new_epoch, job.total_files, job.pending_files), (1, 2, 2, 2));
+        // First batch
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNew1".to_string())]);
+        assert_eq!(contract.get_rotation_pending_files("group1".to_string(), None, None), vec![trans_ids[1].clone()]);
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().pending_files, 1);
+        // Second batch then completion
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[1].clone(), "QmNew2".to_string())]);
+        contract.complete_key_rotation("group1".to_string());
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().status, RotationStatus::Completed);
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 2);
+    }
+
+    #[test]
+    fn test_key_rotation_abort_after_timeout() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNew1".to_string())]);
+        // Anyone can abort once the deadline passed
+        let mut context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.block_timestamp(ROTATION_TIMEOUT_NS + 1).build());
+        contract.abort_key_rotation("group1".to_string(), None);
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().status, RotationStatus::Aborted);
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 1);
+        assert_eq!(contract.get_transaction(trans_ids[0].clone()).unwrap().ipfs_hash, "QmTest");
+        let group = contract.groups.get("group1").unwrap();
+        assert_eq!(group.group_key, Some("symmetric_key_123".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "1 files still pending re-encryption")]
+    fn test_complete_key_rotation_pending() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNew1".to_string())]);
+        contract.complete_key_rotation("group1".to_string());
+    }
+
+    #[test]
+    fn test_rotation_with_wrapped_keys() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let keys = trans_ids.iter().map(|trans_id| (trans_id.clone(), format!("wrapped-{}-e1", trans_id))).collect();
+        contract.submit_wrapped_keys("group1".to_string(), keys);
+        // Rotation only re-wraps keys, CIDs stay unchanged
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        let keys = trans_ids.iter().map(|trans_id| (trans_id.clone(), format!("wrapped-{}-e2", trans_id))).collect();
+        contract.submit_wrapped_keys("group1".to_string(), keys);
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().pending_files, 0);
+        contract.complete_key_rotation("group1".to_string());
+        let tx = contract.get_transaction(trans_ids[0].clone()).unwrap();
+        assert_eq!(tx.ipfs_hash, "QmTest");
+        // Members read the wrapped keys
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        let wrapped_key = contract.get_wrapped_key(trans_ids[0].clone()).unwrap();
+        assert_eq!(wrapped_key, WrappedKey { wrapped_key: format!("wrapped-{}-e2", trans_ids[0]), epoch: 2 });
+        assert_eq!(contract.get_wrapped_keys("group1".to_string(), None, None).len(), 2);
+    }
+
+    #[test]
+    fn test_abort_rotation_restores_wrapped_keys() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e2".to_string())]);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[1].clone(), "wrapped-e2".to_string())]);
+        contract.abort_key_rotation("group1".to_string(), None);
+        let tx = contract.get_transaction(trans_ids[0].clone()).unwrap();
+        assert_eq!(tx.wrapped_key, Some(WrappedKey { wrapped_key: "wrapped-e1".to_string(), epoch: 1 }));
+        assert_eq!(contract.get_transaction(trans_ids[1].clone()).unwrap().wrapped_key, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Key rotation in progress")]
+    fn test_record_transaction_during_rotation() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        let attestation = attest(&mut contract, "group1", "user.near", "ghi789", "QmTest", 1_000);
+        contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "ghi789".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_record_transaction_after_aborted_rotation() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.abort_key_rotation("group1".to_string(), None);
+        // Uploads resume under the restored key and epoch
+        let attestation = attest(&mut contract, "group1", "user.near", "ghi789", "QmTest", 1_000);
+        let trans_id = contract.record_transaction(
+            "group1".to_string(),
+            "user.near".parse().unwrap(),
+            "ghi789".to_string(),
+            "QmTest".to_string(),
+            U64(1_000),
+            None,
+            None,
+            attestation,
+            Some("wrapped-e1".to_string()),
+            None,
+        );
+        assert_eq!(contract.get_transaction(trans_id).unwrap().wrapped_key.unwrap().epoch, 1);
+        assert_eq!(contract.groups.get("group1").unwrap().group_key, Some("symmetric_key_123".to_string()));
+    }
+
+    #[test]
+    fn test_aborted_rotation_epoch_not_reused() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.abort_key_rotation("group1".to_string(), None);
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 1);
+        // The next rotation skips the aborted epoch 2
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_789".to_string());
+        let job = contract.get_rotation_job("group1".to_string()).unwrap();
+        assert_eq!((job.previous_epoch, job.new_epoch), (1, 3));
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 3);
+    }
+
+    #[test]
+    fn test_embargoed_file() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
+        assert_eq!(contract.get_embargoed_files("group1".to_string()), vec![(trans_ids[0].clone(), U64(1_000))]);
+        // Members don't see it before release
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        assert!(contract.get_transaction(trans_ids[0].clone()).is_none());
+        assert_eq!(contract.get_transactions_for_group("group1".to_string()).len(), 1);
+        assert!(contract.get_wrapped_keys("group1".to_string(), None, None).is_empty());
+        assert!(!contract.can_access_file(trans_ids[0].clone(), "user.near".parse().unwrap()));
+        // Released once the block time passes
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.block_timestamp(1_000).build());
+        assert!(contract.get_transaction(trans_ids[0].clone()).is_some());
+        assert_eq!(contract.get_wrapped_key(trans_ids[0].clone()).unwrap().wrapped_key, "wrapped-e1");
+        // Owner moves the release back out
+        let mut context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.block_timestamp(1_000).build());
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(5_000)));
+        assert!(contract.get_unreleased_transaction(trans_ids[0].clone()).is_some());
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.block_timestamp(1_000).build());
+        assert!(contract.get_transaction(trans_ids[0].clone()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "File is under embargo")]
+    fn test_embargoed_wrapped_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_wrapped_key(trans_ids[0].clone());
+    }
+
+    #[test]
+    fn test_redeem_share() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        contract.store_file_metadata(trans_ids[0].clone(), "{\"title\":\"Demo\"}".to_string());
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: Some("press.near".parse().unwrap()),
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Metadata],
+            nonce: U64(1),
+            wrapped_key: None,
+        };
+        let signature = sign_share(&key, &capability);
+        // Grantee reads the metadata without joining the group
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_share(capability, signature);
+        assert_eq!(contract.get_file_metadata(trans_ids[0].clone()), Some("{\"title\":\"Demo\"}".to_string()));
+        assert!(!contract.is_authorized("group1".to_string(), "press.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group members, owner, or devbot agents can view metadata")]
+    fn test_revoked_share() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: None,
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Metadata, SharePermission::Key],
+            nonce: U64(7),
+            wrapped_key: Some("press-wrapped-key".to_string()),
+        };
+        let signature = sign_share(&key, &capability);
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_share(capability, signature);
+        // Owner revokes before expiry
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.revoke_share("group1".to_string(), U64(7));
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.get_file_metadata(trans_ids[0].clone());
+    }
+
+    #[test]
+    fn test_redeem_key_share() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: Some("press.near".parse().unwrap()),
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Key],
+            nonce: U64(2),
+            wrapped_key: Some("press-wrapped-key".to_string()),
+        };
+        let signature = sign_share(&key, &capability);
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_share(capability, signature);
+        assert_eq!(contract.get_shared_key(trans_ids[0].clone()), "press-wrapped-key");
+        assert!(!contract.is_authorized("group1".to_string(), "press.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Key shares must carry the data key wrapped for the grantee")]
+    fn test_redeem_key_share_without_wrapped_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: None,
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Key],
+            nonce: U64(3),
+            wrapped_key: None,
+        };
+        let signature = sign_share(&key, &capability);
+        let context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.redeem_share(capability, signature);
     }
 
     #[test]
-    fn test_register_group() {
+    #[should_panic(expected = "Share expired")]
+    fn test_redeem_share_expired() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        assert!(contract.groups.contains_key(&"group1".to_string()));
-        assert_eq!(get_logs(), vec!["Group group1 registered by auth-agent.devbot.near"]);
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: None,
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Key],
+            nonce: U64(1),
+            wrapped_key: Some("press-wrapped-key".to_string()),
+        };
+        let signature = sign_share(&key, &capability);
+        let mut context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.block_timestamp(1_000).build());
+        contract.redeem_share(capability, signature);
     }
 
     #[test]
-    #[should_panic(expected = "Only contract owner or devbot agents can register a group")]
-    fn test_register_group_unauthorized() {
-        let context = setup_context("devbot.near".parse().unwrap());
+    fn test_delegate_access() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        let context = setup_context("random.near".parse().unwrap());
+        setup_group_files(&mut contract);
+        // Member delegates to a listening app
+        let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        contract.register_group("group1".to_string());
+        contract.delegate_access("group1".to_string(), "player.near".parse().unwrap(), U64(1_000));
+        assert!(contract.is_authorized("group1".to_string(), "player.near".parse().unwrap()));
+        assert_eq!(contract.get_delegates("group1".to_string(), "user.near".parse().unwrap()), vec!["player.near".parse::<AccountId>().unwrap()]);
+        // Expired delegations lapse
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.block_timestamp(1_000).build());
+        assert!(!contract.is_authorized("group1".to_string(), "player.near".parse().unwrap()));
     }
 
     #[test]
-    fn test_add_group_member() {
+    fn test_delegation_cleanup_on_revoke() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-
-        // Trigger cross-contract call
+        setup_group_files(&mut contract);
+        let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-
-        // Simulate successful promise result
-        let context = setup_context("devbot.near".parse().unwrap());
+        contract.delegate_access("group1".to_string(), "player.near".parse().unwrap(), U64(1_000));
+        // Delegator loses membership
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-
-        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
-        assert_eq!(get_logs().last().unwrap(), "User user.near added to group group1");
+        contract.revoke_group_member("group1".to_string(), "user.near".parse().unwrap());
+        assert!(!contract.is_authorized("group1".to_string(), "player.near".parse().unwrap()));
+        assert!(contract.get_delegation("group1".to_string(), "player.near".parse().unwrap()).is_none());
+        assert!(contract.get_delegates("group1".to_string(), "user.near".parse().unwrap()).is_empty());
     }
 
     #[test]
-    #[should_panic(expected = "User does not own a 1000fans token")]
-    fn test_add_group_member_no_nft() {
+    #[should_panic(expected = "Only group members can delegate access")]
+    fn test_delegate_access_non_member() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
         contract.register_group("group1".to_string());
+        let context = setup_context("random.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.delegate_access("group1".to_string(), "player.near".parse().unwrap(), U64(1_000));
+    }
 
-        // Trigger cross-contract call
+    #[test]
+    fn test_sub_group_inheritance() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        contract.register_group("label".to_string());
+        contract.register_group("artist".to_string());
+        contract.register_group("project".to_string());
+        contract.set_parent_group("artist".to_string(), Some("label".to_string()));
+        contract.set_parent_group("project".to_string(), Some("artist".to_string()));
+        contract.insert_group_member("label", &"user.near".parse().unwrap());
+        contract.insert_group_member("project", &"fan.near".parse().unwrap());
+        assert!(contract.is_authorized("project".to_string(), "user.near".parse().unwrap()));
+        assert!(!contract.is_authorized("label".to_string(), "fan.near".parse().unwrap()));
+        assert_eq!(contract.get_child_groups("label".to_string()), vec!["artist".to_string()]);
+        assert_eq!(contract.get_effective_members("project".to_string()).len(), 2);
+        // Artist opts out, which also cuts off the label for its projects
+        contract.set_inherit_membership("artist".to_string(), false);
+        assert!(!contract.is_authorized("project".to_string(), "user.near".parse().unwrap()));
+        assert_eq!(contract.get_effective_members("project".to_string()), vec!["fan.near".parse::<AccountId>().unwrap()]);
+    }
 
-        // Simulate empty token list
-        let context = setup_context("devbot.near".parse().unwrap());
+    #[test]
+    #[should_panic(expected = "Parent group would create a cycle")]
+    fn test_sub_group_cycle() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        contract.register_group("label".to_string());
+        contract.register_group("artist".to_string());
+        contract.set_parent_group("artist".to_string(), Some("label".to_string()));
+        contract.set_parent_group("label".to_string(), Some("artist".to_string()));
     }
 
     #[test]
-    #[should_panic(expected = "Token group_id does not match group")]
-    fn test_add_group_member_wrong_group_id() {
+    fn test_file_link() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
+        let trans_ids = setup_group_files(&mut contract);
+        let context = setup_context("community.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.register_group("community".to_string());
+        contract.store_group_key("community".to_string(), "community_key".to_string());
+        contract.insert_group_member("community", &"fan.near".parse().unwrap());
+        contract.request_file_link(trans_ids[0].clone(), "community".to_string());
+        // Origin owner consents, wrapping the file's data key for the community
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-g1".to_string())]);
+        contract.approve_file_link(trans_ids[0].clone(), "community".to_string(), "wrapped-c1".to_string());
+        assert_eq!(contract.get_linked_files("community".to_string()), vec![trans_ids[0].clone()]);
+        // Community members see, access and decrypt the linked file
+        let context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.build());
+        assert_eq!(contract.get_transactions_for_group("community".to_string()).len(), 1);
+        assert!(contract.can_access_file(trans_ids[0].clone(), "fan.near".parse().unwrap()));
+        let link_key = contract.get_link_key(trans_ids[0].clone(), "community".to_string()).unwrap();
+        assert_eq!((link_key.wrapped_key.as_str(), link_key.epoch), ("wrapped-c1", 1));
+        // Unlinking leaves the origin group untouched
+        let context = setup_context("community.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.unlink_file(trans_ids[0].clone(), "community".to_string());
+        assert!(!contract.can_access_file(trans_ids[0].clone(), "fan.near".parse().unwrap()));
+        assert!(contract.get_link_key(trans_ids[0].clone(), "community".to_string()).is_none());
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        assert_eq!(contract.get_transactions_for_group("group1".to_string()).len(), 2);
+    }
 
-        // Trigger cross-contract call
+    #[test]
+    #[should_panic(expected = "Only the origin group owner can approve file links")]
+    fn test_approve_file_link_unauthorized() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let context = setup_context("community.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        contract.register_group("community".to_string());
+        contract.request_file_link(trans_ids[0].clone(), "community".to_string());
+        contract.approve_file_link(trans_ids[0].clone(), "community".to_string(), "wrapped-c1".to_string());
+    }
 
-        // Simulate token with wrong group_id
+    #[test]
+    fn test_pause_scopes() {
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group2")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        contract.pause(None, Some(PauseScope::Keys));
+        contract.pause(None, None);
+        assert_eq!(contract.get_pause_status().len(), 2);
+        contract.unpause(None, None);
+        assert_eq!(contract.get_pause_status(), vec![(None, Some(PauseScope::Keys))]);
+        // Other classes keep working
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.register_group("group1".to_string());
+        assert_eq!(get_logs(), vec!["Group group1 registered by auth-agent.devbot.near"]);
     }
 
     #[test]
-    fn test_revoke_group_member() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    #[should_panic(expected = "Keys methods of group group1 are paused")]
+    fn test_pause_group_scope() {
+        let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        contract.register_group("group1".to_string());
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Revoke member
+        contract.pause(Some("group1".to_string()), Some(PauseScope::Keys));
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.revoke_group_member("group1".to_string(), "user.near".parse().unwrap());
-        assert!(!contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
-        assert_eq!(get_logs().last().unwrap(), "User user.near revoked from group group1");
+        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
     }
 
     #[test]
-    fn test_is_authorized() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    #[should_panic(expected = "Contract is paused")]
+    fn test_pause_global() {
+        let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
+        contract.pause(None, None);
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
         contract.register_group("group1".to_string());
-        // Add member
+    }
+
+    #[test]
+    fn test_council_proposal() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap(), "carol.near".parse().unwrap()], 2);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let proposal_id = contract.propose(CouncilAction::Pause { group_id: None, scope: Some(PauseScope::Keys) });
+        let context = setup_context("bob.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.approve_proposal(proposal_id);
+        let _ = contract.execute_proposal(proposal_id);
+        assert!(contract.get_proposal(proposal_id).unwrap().executed);
+        // The self-call performs the action
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
-        assert!(!contract.is_authorized("group1".to_string(), "other.near".parse().unwrap()));
+        contract.pause(None, Some(PauseScope::Keys));
+        assert_eq!(contract.get_pause_status(), vec![(None, Some(PauseScope::Keys))]);
     }
 
     #[test]
-    fn test_record_transaction() {
+    #[should_panic(expected = "Only contract owner can pause")]
+    fn test_council_replaces_owner() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 2);
+        contract.pause(None, None);
+    }
+
+    #[test]
+    fn test_council_replaces_owner_bypasses() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
+        let trans_ids = setup_group_files(&mut contract);
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 2);
+        // Every owner bypass now needs the council
+        type OwnerCall<'a> = Box<dyn Fn(&mut Contract) + 'a>;
+        let trans_id = &trans_ids[0];
+        let calls: Vec<OwnerCall> = vec![
+            Box::new(|contract| contract.set_protocol_fee(1_000)),
+            Box::new(|contract| contract.set_group_signing_key("group1".to_string(), signing_public_key(&signing_key()))),
+            Box::new(|contract| contract.add_group_manager("group1".to_string(), "owner.near".parse().unwrap())),
+            Box::new(|contract| contract.remove_group_manager("group1".to_string(), "manager.near".parse().unwrap())),
+            Box::new(|contract| contract.revoke_group_member("group1".to_string(), "user.near".parse().unwrap())),
+            Box::new(|contract| contract.set_release_at(trans_id.clone(), None)),
+            Box::new(|contract| contract.set_chunk_root(trans_id.clone(), hex::encode([0u8; 32]), 1)),
+            Box::new(|contract| contract.approve_file_link(trans_id.clone(), "group1".to_string(), "wrapped".to_string())),
+            Box::new(|contract| contract.unlink_file(trans_id.clone(), "group1".to_string())),
+            Box::new(|contract| contract.set_file_price(trans_id.clone(), Some(U128(1_000)))),
+            Box::new(|contract| contract.revoke_share("group1".to_string(), U64(1))),
+        ];
+        for (index, call) in calls.iter().enumerate() {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| call(&mut contract)));
+            let error = result.expect_err(&format!("Owner bypass {} still accepted", index));
+            let message = error
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| error.downcast_ref::<&str>().map(|message| message.to_string()))
+                .unwrap_or_default();
+            assert!(message.starts_with("Only"), "Unexpected failure for call {}: {}", index, message);
+        }
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough approvals")]
+    fn test_council_threshold() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 2);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        let proposal_id = contract.propose(CouncilAction::SetOwner { new_owner: "alice.near".parse().unwrap() });
+        let _ = contract.execute_proposal(proposal_id);
+    }
+
+    #[test]
+    fn test_deploy_upgrade() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let code = b"\0asm new contract code".to_vec();
+        contract.stage_upgrade(hex::encode(env::sha256(&code)));
+        let staged = contract.get_staged_upgrade().unwrap();
+        assert_eq!(staged.deployable_at, U64(DEFAULT_UPGRADE_DELAY_NS));
+        // Anyone deploys the staged code after the delay
+        let mut context = setup_context("random.near".parse().unwrap());
+        let mut vm_context = context.block_timestamp(DEFAULT_UPGRADE_DELAY_NS).build();
+        vm_context.input = code.into();
+        testing_env!(vm_context);
+        let _ = contract.deploy_upgrade();
+        assert!(contract.get_staged_upgrade().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Upgrade timelock has not passed")]
+    fn test_deploy_upgrade_early() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        assert_eq!(get_logs().last().unwrap(), &format!("Transaction recorded: {}", trans_id));
-        let tx = contract.get_transaction(trans_id.clone()).unwrap();
-        assert_eq!(tx.group_id, "group1");
-        assert_eq!(tx.ipfs_hash, "QmTest");
+        let mut contract = Contract::new();
+        let code = b"\0asm new contract code".to_vec();
+        contract.stage_upgrade(hex::encode(env::sha256(&code)));
+        let mut context = setup_context("owner.near".parse().unwrap());
+        let mut vm_context = context.block_timestamp(DEFAULT_UPGRADE_DELAY_NS - 1).build();
+        vm_context.input = code.into();
+        testing_env!(vm_context);
+        let _ = contract.deploy_upgrade();
     }
 
     #[test]
-    fn test_store_group_key() {
-        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+    #[should_panic(expected = "Code does not match the staged hash")]
+    fn test_deploy_upgrade_wrong_code() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.stage_upgrade(hex::encode(env::sha256(b"\0asm new contract code")));
+        let mut context = setup_context("owner.near".parse().unwrap());
+        let mut vm_context = context.block_timestamp(DEFAULT_UPGRADE_DELAY_NS).build();
+        vm_context.input = b"\0asm other code".to_vec().into();
+        testing_env!(vm_context);
+        let _ = contract.deploy_upgrade();
+    }
+
+    #[test]
+    #[should_panic(expected = "Upgrade timelock has not passed")]
+    fn test_lowered_upgrade_delay_keeps_notice() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_upgrade_delay(U64(MIN_UPGRADE_DELAY_NS));
+        assert_eq!(contract.get_upgrade_delay(), U64(DEFAULT_UPGRADE_DELAY_NS));
+        assert_eq!(contract.get_pending_upgrade_delay(), Some((U64(MIN_UPGRADE_DELAY_NS), U64(DEFAULT_UPGRADE_DELAY_NS))));
+        // Staging right away still uses the old delay
+        let code = b"\0asm new contract code".to_vec();
+        contract.stage_upgrade(hex::encode(env::sha256(&code)));
+        let mut context = setup_context("owner.near".parse().unwrap());
+        let mut vm_context = context.block_timestamp(MIN_UPGRADE_DELAY_NS).build();
+        vm_context.input = code.into();
+        testing_env!(vm_context);
+        let _ = contract.deploy_upgrade();
+    }
+
+    #[test]
+    fn test_lowered_upgrade_delay_applies_after_notice() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_upgrade_delay(U64(MIN_UPGRADE_DELAY_NS));
+        let mut context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.block_timestamp(DEFAULT_UPGRADE_DELAY_NS).build());
+        assert_eq!(contract.get_upgrade_delay(), U64(MIN_UPGRADE_DELAY_NS));
+        assert!(contract.get_pending_upgrade_delay().is_none());
+        contract.stage_upgrade(hex::encode(env::sha256(b"\0asm new contract code")));
+        assert_eq!(contract.get_staged_upgrade().unwrap().deployable_at, U64(DEFAULT_UPGRADE_DELAY_NS + MIN_UPGRADE_DELAY_NS));
+    }
+
+    #[test]
+    #[should_panic(expected = "Upgrade delay is below the minimum")]
+    fn test_upgrade_delay_minimum() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_upgrade_delay(U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid invitation signature")]
+    fn test_redeem_invitation_signed_for_other_contract() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
         contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        let group = contract.groups.get(&"group1".to_string()).expect("Group not found");
-        assert_eq!(group.group_key, Some("symmetric_key_123".to_string()));
-        assert_eq!(get_logs().last().unwrap(), "Group key stored for group group1");
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: None,
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        // Signed for another deployment trusting the same key
+        let mut context = setup_context("press.near".parse().unwrap());
+        testing_env!(context.current_account_id("other-dfs.near".parse().unwrap()).build());
+        let signature = sign_invitation(&key, &invitation);
+        testing_env!(setup_context("press.near".parse().unwrap()).build());
+        contract.redeem_invitation(invitation, signature);
     }
 
     #[test]
-    #[should_panic(expected = "Only group owner or devbot agents can store group key")]
-    fn test_store_group_key_unauthorized() {
+    #[should_panic(expected = "Invalid invitation signature")]
+    fn test_redeem_invitation_with_share_signature() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
         contract.register_group("group1".to_string());
-        let context = setup_context("random.near".parse().unwrap());
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let invitation = Invitation {
+            group_id: "group1".to_string(),
+            invitee: None,
+            expires_at: U64(1_000),
+            nonce: U64(1),
+        };
+        // Same bytes signed under another type tag
+        let signature = hex::encode(key.sign(&signed_message(SHARE_TAG, &invitation)).to_bytes());
+        let context = setup_context("press.near".parse().unwrap());
         testing_env!(context.build());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
+        contract.redeem_invitation(invitation, signature);
     }
 
     #[test]
-    #[should_panic(expected = "Group key cannot be empty")]
-    fn test_store_group_key_empty() {
-        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+    fn test_purchased_file_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "".to_string());
+        let trans_ids = setup_group_files(&mut contract);
+        contract.set_file_price(trans_ids[0].clone(), Some(U128(1_000)));
+        let mut context = setup_context("buyer.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_000)).build());
+        contract.purchase_file(trans_ids[0].clone());
+        assert_eq!(contract.get_purchased_key(trans_ids[0].clone()), None);
+        // The storage agent wraps the data key for the buyer
+        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.deliver_file_key(trans_ids[0].clone(), "buyer.near".parse().unwrap(), "wrapped_for_buyer".to_string());
+        let context = setup_context("buyer.near".parse().unwrap());
+        testing_env!(context.build());
+        assert_eq!(contract.get_purchased_key(trans_ids[0].clone()), Some("wrapped_for_buyer".to_string()));
     }
 
     #[test]
-    fn test_get_group_key() {
+    #[should_panic(expected = "buyer.near has not purchased this file")]
+    fn test_deliver_file_key_without_purchase() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        // Add member
+        let trans_ids = setup_group_files(&mut contract);
+        contract.deliver_file_key(trans_ids[0].clone(), "buyer.near".parse().unwrap(), "wrapped_for_buyer".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach at least")]
+    fn test_request_group_key_without_deposit() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        let mut contract = Contract::new();
+        setup_group_files(&mut contract);
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.request_group_key("group1".to_string());
+    }
+
+    #[test]
+    fn test_request_group_key_refunds_excess() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Get key
+        let mut contract = Contract::new();
+        setup_group_files(&mut contract);
         let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        let key = contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
-        assert_eq!(key, "symmetric_key_123");
+        contract.request_group_key("group1".to_string());
+        let refund = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .find(|receipt| receipt.receiver_id.as_str() == "user.near")
+            .expect("Excess deposit not refunded");
+        assert!(matches!(
+            refund.actions.as_slice(),
+            [near_sdk::mock::MockAction::Transfer { deposit, .. }] if deposit.as_yoctonear() < 1_000_000_000_000_000_000_000_000
+        ));
     }
 
     #[test]
-    #[should_panic(expected = "User not authorized")]
-    fn test_get_group_key_unauthorized() {
+    #[should_panic(expected = "Attach at least")]
+    fn test_delegate_access_without_deposit() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        let context = setup_context("user.near".parse().unwrap());
+        setup_group_files(&mut contract);
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.delegate_access("group1".to_string(), "assistant.near".parse().unwrap(), U64(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach at least")]
+    fn test_redeem_bearer_share_without_deposit() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        let key = signing_key();
+        contract.set_group_signing_key("group1".to_string(), signing_public_key(&key));
+        let capability = ShareCapability {
+            trans_id: trans_ids[0].clone(),
+            grantee: None,
+            expires_at: U64(1_000),
+            permissions: vec![SharePermission::Metadata],
+            nonce: U64(1),
+            wrapped_key: None,
+        };
+        let signature = sign_share(&key, &capability);
+        let mut context = setup_context("anyone.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.redeem_share(capability, signature);
     }
 
     #[test]
-    #[should_panic(expected = "Only group owner, devbot agents, or the user can retrieve the group key")]
-    fn test_get_group_key_wrong_caller() {
+    fn test_abort_key_rotation_in_batches() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        // Add member
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![
+            (trans_ids[0].clone(), "QmNew1".to_string()),
+            (trans_ids[1].clone(), "QmNew2".to_string()),
+        ]);
+        // The key is restored at once, files one batch at a time
+        contract.abort_key_rotation("group1".to_string(), Some(1));
+        let job = contract.get_rotation_job("group1".to_string()).unwrap();
+        assert_eq!((job.status, job.changed_files), (RotationStatus::Aborting, 1));
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 1);
+        assert_eq!(contract.groups.get("group1").unwrap().group_key, Some("symmetric_key_123".to_string()));
+        assert_eq!(contract.get_transaction(trans_ids[1].clone()).unwrap().ipfs_hash, "QmTest");
+        assert_eq!(contract.get_transaction(trans_ids[0].clone()).unwrap().ipfs_hash, "QmNew1");
+        // Anyone can finish an abort
+        let context = setup_context("random.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        contract.abort_key_rotation("group1".to_string(), Some(1));
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().status, RotationStatus::Aborted);
+        assert_eq!(contract.get_transaction(trans_ids[0].clone()).unwrap().ipfs_hash, "QmTest");
+    }
+
+    #[test]
+    #[should_panic(expected = "Key rotation is being aborted")]
+    fn test_update_group_files_while_aborting() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Wrong caller
-        let context = setup_context("other.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![
+            (trans_ids[0].clone(), "QmNew1".to_string()),
+            (trans_ids[1].clone(), "QmNew2".to_string()),
+        ]);
+        contract.abort_key_rotation("group1".to_string(), Some(1));
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNew3".to_string())]);
+    }
+
+    #[test]
+    fn test_second_rotation_ignores_completed_history() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.get_group_key("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![
+            (trans_ids[0].clone(), "QmNew1".to_string()),
+            (trans_ids[1].clone(), "QmNew2".to_string()),
+        ]);
+        contract.complete_key_rotation("group1".to_string());
+        // The next rotation starts with every file pending and aborts back to the first rotation's CIDs
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_789".to_string());
+        assert_eq!(contract.get_rotation_job("group1".to_string()).unwrap().pending_files, 2);
+        assert_eq!(contract.get_rotation_pending_files("group1".to_string(), None, None).len(), 2);
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNewer1".to_string())]);
+        contract.abort_key_rotation("group1".to_string(), None);
+        assert_eq!(contract.get_transaction(trans_ids[0].clone()).unwrap().ipfs_hash, "QmNew1");
+        assert_eq!(contract.get_transaction(trans_ids[1].clone()).unwrap().ipfs_hash, "QmNew2");
+        assert_eq!(contract.get_key_epoch("group1".to_string()), 2);
     }
 
     #[test]
-    fn test_rotate_group_key() {
-        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+    fn test_embargoed_transaction_hidden_from_member_passing_owner_id() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        contract.rotate_group_key("group1".to_string(), "new_symmetric_key_456".to_string());
-        let group = contract.groups.get(&"group1".to_string()).expect("Group not found");
-        assert_eq!(group.group_key, Some("new_symmetric_key_456".to_string()));
-        assert_eq!(get_logs().last().unwrap(), "Group key rotated for group group1");
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
+        let context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.build());
+        // A member cannot lift the embargo by claiming to be the owner
+        assert!(contract.get_transaction(trans_ids[0].clone()).is_none());
     }
 
     #[test]
-    #[should_panic(expected = "Only group owner or devbot agents can rotate group key")]
-    fn test_rotate_group_key_unauthorized() {
+    #[should_panic(expected = "Only group owner or devbot agents can view unreleased files")]
+    fn test_get_unreleased_transaction_by_member() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        let context = setup_context("random.near".parse().unwrap());
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
+        let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        contract.rotate_group_key("group1".to_string(), "new_symmetric_key_456".to_string());
+        contract.get_unreleased_transaction(trans_ids[0].clone());
     }
 
     #[test]
-    #[should_panic(expected = "New group key cannot be empty")]
-    fn test_rotate_group_key_empty() {
-        let context = setup_context("storage-agent.devbot.near".parse().unwrap());
+    #[should_panic(expected = "Embargoed files need a per-file wrapped key")]
+    fn test_embargo_file_without_wrapped_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        contract.store_group_key("group1".to_string(), "symmetric_key_123".to_string());
-        contract.rotate_group_key("group1".to_string(), "".to_string());
+        let trans_ids = setup_group_files(&mut contract);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
     }
 
     #[test]
-    fn test_get_transactions_for_group() {
+    fn test_group_key_does_not_unlock_embargoed_file() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
-        testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        let trans_ids = setup_group_files(&mut contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-e1".to_string())]);
+        contract.set_release_at(trans_ids[0].clone(), Some(U64(1_000)));
+        // The member gets the group key, but neither the file nor its wrapped data key
+        let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
+        assert_eq!(contract.request_group_key("group1".to_string()), "symmetric_key_123");
+        assert!(contract.get_transaction(trans_ids[0].clone()).is_none());
+        assert!(contract.get_wrapped_keys("group1".to_string(), None, None).is_empty());
+        // Lifting the embargo needs no wrapped key
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        let _trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Get transactions
-        let context = setup_context("user.near".parse().unwrap());
-        testing_env!(context.build());
-        let transactions = contract.get_transactions_for_group("group1".to_string());
-        assert_eq!(transactions.len(), 1);
-        assert_eq!(transactions[0].ipfs_hash, "QmTest");
+        contract.set_release_at(trans_ids[1].clone(), None);
+    }
+
+    fn setup_linked_file(contract: &mut Contract) -> Vec<String> {
+        let trans_ids = setup_group_files(contract);
+        contract.submit_wrapped_keys("group1".to_string(), vec![(trans_ids[0].clone(), "wrapped-g1".to_string())]);
+        contract.register_group("community".to_string());
+        contract.store_group_key("community".to_string(), "community_key".to_string());
+        contract.insert_group_member("community", &"fan.near".parse().unwrap());
+        contract.request_file_link(trans_ids[0].clone(), "community".to_string());
+        contract.approve_file_link(trans_ids[0].clone(), "community".to_string(), "wrapped-c1".to_string());
+        trans_ids
     }
 
     #[test]
-    #[should_panic(expected = "Only group members, owner, or devbot agents can view transactions")]
-    fn test_get_transactions_for_group_unauthorized() {
+    fn test_link_key_dropped_on_target_rotation() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        let context = setup_context("random.near".parse().unwrap());
+        let trans_ids = setup_linked_file(&mut contract);
+        contract.rotate_group_key("community".to_string(), "community_key_2".to_string());
+        assert!(contract.get_link_key(trans_ids[0].clone(), "community".to_string()).is_none());
+        // Re-wrapped under the new community key
+        contract.submit_link_key(trans_ids[0].clone(), "community".to_string(), "wrapped-c2".to_string());
+        let context = setup_context("fan.near".parse().unwrap());
         testing_env!(context.build());
-        contract.get_transactions_for_group("group1".to_string());
+        let link_key = contract.get_link_key(trans_ids[0].clone(), "community".to_string()).unwrap();
+        assert_eq!((link_key.wrapped_key.as_str(), link_key.epoch), ("wrapped-c2", 2));
     }
 
     #[test]
-    fn test_update_group_files() {
+    fn test_link_key_dropped_on_reencryption() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        let trans_ids = setup_linked_file(&mut contract);
+        contract.rotate_group_key("group1".to_string(), "symmetric_key_456".to_string());
+        contract.update_group_files("group1".to_string(), vec![(trans_ids[0].clone(), "QmNew1".to_string())]);
+        assert!(contract.get_link_key(trans_ids[0].clone(), "community".to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Linked files need a per-file wrapped key")]
+    fn test_approve_file_link_without_wrapped_key() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.register_group("community".to_string());
+        contract.request_file_link(trans_ids[1].clone(), "community".to_string());
+        contract.approve_file_link(trans_ids[1].clone(), "community".to_string(), "wrapped-c1".to_string());
+    }
+
+    #[test]
+    fn test_pause_events() {
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
-        testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
+        let mut contract = Contract::new();
+        contract.pause(None, Some(PauseScope::Payments));
+        assert_eq!(
+            get_logs(),
+            vec![r#"EVENT_JSON:{"standard":"dfs_manager","version":"1.0.0","event":"pause","data":[{"group_id":null,"scope":"Payments"}]}"#]
+        );
+        contract.unpause(None, Some(PauseScope::Payments));
+        assert_eq!(
+            get_logs().last().unwrap(),
+            r#"EVENT_JSON:{"standard":"dfs_manager","version":"1.0.0","event":"unpause","data":[{"group_id":null,"scope":"Payments"}]}"#
         );
-        // Update files
-        contract.update_group_files("group1".to_string(), vec!["QmNewHash".to_string()]);
-        let tx = contract.get_transaction(trans_id).unwrap();
-        assert_eq!(tx.ipfs_hash, "QmNewHash");
-        assert_eq!(get_logs().last().unwrap(), "IPFS hashes updated for group group1");
     }
 
     #[test]
-    #[should_panic(expected = "Number of new IPFS hashes must match number of transactions")]
-    fn test_update_group_files_mismatch() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    fn test_admin_methods_ignore_pause() {
+        let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        contract.pause(None, None);
+        contract.set_protocol_fee(100);
+        contract.register_pinning_provider("pinner.near".parse().unwrap(), "https://pin.example".to_string());
+        contract.remove_pinning_provider("pinner.near".parse().unwrap());
+        assert_eq!(contract.get_protocol_fee(), 100);
+    }
+
+    #[test]
+    fn test_uploads_pause_leaves_payments_open() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let mut contract = Contract::new();
+        let trans_ids = setup_group_files(&mut contract);
+        contract.set_file_price(trans_ids[0].clone(), Some(U128(1_000)));
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        contract.pause(None, Some(PauseScope::Uploads));
+        contract.pause(None, Some(PauseScope::Membership));
+        let context = setup_context("buyer.near".parse().unwrap());
         testing_env!(context.build());
-        contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Update with mismatch
-        contract.update_group_files("group1".to_string(), vec!["QmNewHash1".to_string(), "QmNewHash2".to_string()]);
+        contract.purchase_file(trans_ids[0].clone());
+        assert!(contract.can_access_file(trans_ids[0].clone(), "buyer.near".parse().unwrap()));
     }
 
     #[test]
-    #[should_panic(expected = "Only group owner or devbot agents can update group files")]
-    fn test_update_group_files_unauthorized() {
+    #[should_panic(expected = "Payments methods are paused")]
+    fn test_purchase_file_payments_paused() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        let context = setup_context("random.near".parse().unwrap());
+        let trans_ids = setup_group_files(&mut contract);
+        contract.set_file_price(trans_ids[0].clone(), Some(U128(1_000)));
+        let context = setup_context("devbot.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.pause(None, Some(PauseScope::Payments));
+        let context = setup_context("buyer.near".parse().unwrap());
         testing_env!(context.build());
-        contract.update_group_files("group1".to_string(), vec!["QmNewHash".to_string()]);
+        contract.purchase_file(trans_ids[0].clone());
     }
 
     #[test]
-    fn test_store_file_metadata() {
+    #[should_panic(expected = "Membership methods of group group1 are paused")]
+    fn test_add_member_callback_after_pause() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
         contract.register_group("group1".to_string());
-        // Add member
-        testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
+        contract.pause(Some("group1".to_string()), Some(PauseScope::Membership));
+        // The callback runs as the contract itself, which is otherwise exempt
         contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
-        testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Store metadata
-        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
-        let metadata = contract.get_file_metadata(trans_id.clone()).unwrap();
-        assert_eq!(metadata, "file_size:1MB");
-        assert_eq!(get_logs().last().unwrap(), &format!("Metadata stored for transaction {}", trans_id));
     }
 
     #[test]
-    #[should_panic(expected = "Only devbot agents can store file metadata")]
-    fn test_store_file_metadata_unauthorized() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    fn test_failed_proposal_can_be_retried() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 1);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        let proposal_id = contract.propose(CouncilAction::Pause { group_id: None, scope: Some(PauseScope::Keys) });
+        let _ = contract.execute_proposal(proposal_id);
+        assert!(contract.get_proposal(proposal_id).unwrap().executed);
+        setup_callback_context(vec![PromiseResult::Failed]);
+        contract.execute_proposal_callback(proposal_id);
+        assert!(!contract.get_proposal(proposal_id).unwrap().executed);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        let _ = contract.execute_proposal(proposal_id);
+        setup_callback_context(vec![PromiseResult::Successful(vec![])]);
+        contract.execute_proposal_callback(proposal_id);
+        assert!(contract.get_proposal(proposal_id).unwrap().executed);
+        assert_eq!(get_logs().last().unwrap(), &format!("Proposal {} executed", proposal_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Proposal already executed")]
+    fn test_proposal_in_flight_not_executed_twice() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Unauthorized caller
-        let context = setup_context("random.near".parse().unwrap());
+        let mut contract = Contract::new();
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 1);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.store_file_metadata(trans_id, "file_size:1MB".to_string());
+        let proposal_id = contract.propose(CouncilAction::Pause { group_id: None, scope: Some(PauseScope::Keys) });
+        let _ = contract.execute_proposal(proposal_id);
+        let _ = contract.execute_proposal(proposal_id);
     }
 
     #[test]
-    #[should_panic(expected = "Metadata cannot be empty")]
-    fn test_store_file_metadata_empty() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    fn test_owner_registers_group_under_council() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 2);
         contract.register_group("group1".to_string());
-        // Add member
+        assert_eq!(contract.groups.get("group1").unwrap().owner.as_str(), "owner.near");
+    }
+
+    #[test]
+    fn test_fee_recipient_set_by_council() {
+        let context = setup_context("owner.near".parse().unwrap());
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        contract.set_protocol_fee(1_000);
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 1);
+        let context = setup_context("alice.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        let proposal_id = contract.propose(CouncilAction::SetFeeRecipient { fee_recipient: "treasury.near".parse().unwrap() });
+        let _ = contract.execute_proposal(proposal_id);
+        // The self-call performs the action
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        contract.set_fee_recipient("treasury.near".parse().unwrap());
+        let context = setup_context("owner.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Empty metadata
-        contract.store_file_metadata(trans_id, "".to_string());
+        contract.register_group("group1".to_string());
+        contract.set_membership_price("group1".to_string(), Some(U128(1_000)));
+        let mut context = setup_context("fan.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_000)).build());
+        contract.buy_membership("group1".to_string());
+        assert_eq!(contract.get_proceeds("treasury.near".parse().unwrap()), U128(100));
+        assert_eq!(contract.get_proceeds("owner.near".parse().unwrap()), U128(0));
     }
 
     #[test]
-    fn test_get_file_metadata() {
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+    #[should_panic(expected = "Only contract owner can set the fee recipient")]
+    fn test_set_fee_recipient_by_owner_under_council() {
+        let context = setup_context("owner.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
-        contract.register_group("group1".to_string());
-        // Add member
+        contract.set_council(vec!["alice.near".parse().unwrap(), "bob.near".parse().unwrap()], 2);
+        contract.set_fee_recipient("owner.near".parse().unwrap());
+    }
+
+    #[test]
+    fn test_migrate_current_state_is_noop() {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
+        {
+            let mut contract = Contract::new();
+            setup_group_files(&mut contract);
+            env::state_write(&contract);
+        }
+        // A later upgrade chains migrate again
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_transactions_for_group("group1".to_string()).len(), 2);
+        assert_eq!(contract.get_storage_usage("group1".to_string()).usage.file_count, U64(2));
+        assert!(contract.legacy_transactions.is_none());
+    }
+
+    #[test]
+    fn test_migrate_transactions_in_batches() {
         let context = setup_context("devbot.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
-        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
+        {
+            let mut old = ContractV0 {
+                owner: "devbot.near".parse().unwrap(),
+                transactions: IterableMap::new(b"t"),
+                groups: LookupMap::new(b"g"),
+                group_members: LookupMap::new(b"m"),
+                file_metadata: LookupMap::new(b"f"),
+            };
+            old.groups.insert("group1".to_string(), Group { owner: "devbot.near".parse().unwrap(), group_key: None });
+            for index in 0..3 {
+                old.transactions.insert(format!("tx{}", index), TransactionV0 {
+                    group_id: "group1".to_string(),
+                    user_id: "user.near".to_string(),
+                    file_hash: format!("hash{}", index),
+                    ipfs_hash: "QmTest".to_string(),
+                });
+            }
+            env::state_write(&old);
+        }
+        let mut contract = Contract::migrate();
+        // Anyone can carry the migration forward
+        let context = setup_context("random.near".parse().unwrap());
         testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
-        );
-        // Store and get metadata
-        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
-        let context = setup_context("user.near".parse().unwrap());
+        assert_eq!(contract.migrate_transactions(Some(2)), U64(1));
+        assert_eq!(contract.get_storage_usage("group1".to_string()).usage.file_count, U64(2));
+        assert_eq!(contract.migrate_transactions(Some(2)), U64(0));
+        assert_eq!(contract.get_storage_usage("group1".to_string()).usage.file_count, U64(3));
+        assert!((0..3).all(|index| contract.get_transaction(format!("tx{}", index)).is_some()));
+    }
+
+    // Register group1 and mint a membership token of it for each holder
+    fn setup_membership_tokens(holders: &[(&str, &str)]) -> Contract {
+        let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        let metadata = contract.get_file_metadata(trans_id).unwrap();
-        assert_eq!(metadata, "file_size:1MB");
+        let mut contract = Contract::new();
+        contract.register_group("group1".to_string());
+        for (token_id, holder) in holders {
+            contract.mint_membership_token("group1".to_string(), token_id.to_string(), holder.parse().unwrap(), None);
+        }
+        contract
+    }
+
+    fn one_yocto_context(predecessor: &str) -> VMContextBuilder {
+        let mut context = setup_context(predecessor.parse().unwrap());
+        context.attached_deposit(NearToken::from_yoctonear(1));
+        context
     }
 
     #[test]
-    #[should_panic(expected = "Only group members, owner, or devbot agents can view metadata")]
-    fn test_get_file_metadata_unauthorized() {
+    fn test_mint_membership_token() {
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
         let mut contract = Contract::new();
         contract.register_group("group1".to_string());
-        // Add member
-        testing_env!(context.build());
-        contract.add_group_member("group1".to_string(), "user.near".parse().unwrap());
-        let context = setup_context("devbot.near".parse().unwrap());
+        let metadata = TokenMetadata { extra: Some(json!({ "tier": "gold" }).to_string()), ..Default::default() };
+        let token = contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), Some(metadata));
+        assert_eq!(token_group_id(&token).as_deref(), Some("group1"));
+        let metadata = token.metadata.unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("group1 membership"));
+        assert!(metadata.extra.unwrap().contains("\"tier\":\"gold\""));
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert_eq!(contract.get_token_group("fan000".to_string()).as_deref(), Some("group1"));
+        assert_eq!(contract.nft_supply_for_owner("user.near".parse().unwrap()).0, 1);
+        assert_eq!(contract.nft_metadata().symbol, "DFSM");
+        assert_eq!(get_logs().last().unwrap(), "Membership token fan000 of group group1 minted for user.near");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only group owner, contract owner, or devbot agents can mint membership tokens")]
+    fn test_mint_membership_token_unauthorized() {
+        let mut contract = setup_membership_tokens(&[]);
+        let context = setup_context("user.near".parse().unwrap());
         testing_env!(context.build());
-        contract.set_mock_promise_result(vec![create_mock_token("user.near".parse().unwrap(), "group1")]);
-        contract.add_group_member_callback("group1".to_string(), "user.near".parse().unwrap());
-        // Record transaction
+        contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), None);
+    }
+
+    #[test]
+    fn test_membership_token_transfer() {
+        let mut contract = setup_membership_tokens(&[("fan000", "user.near")]);
+        testing_env!(one_yocto_context("user.near").build());
+        contract.nft_transfer("alice.near".parse().unwrap(), "fan000".to_string(), None, None);
+        assert!(!contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(contract.is_authorized("group1".to_string(), "alice.near".parse().unwrap()));
+        assert_eq!(contract.nft_token("fan000".to_string()).unwrap().owner_id.as_str(), "alice.near");
+    }
+
+    #[test]
+    fn test_membership_token_transfer_keeps_other_token() {
+        let mut contract = setup_membership_tokens(&[("fan000", "user.near"), ("fan001", "user.near")]);
+        testing_env!(one_yocto_context("user.near").build());
+        contract.nft_transfer("alice.near".parse().unwrap(), "fan000".to_string(), None, None);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(contract.is_authorized("group1".to_string(), "alice.near".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_paid_member_keeps_membership_after_token_transfer() {
+        let mut contract = setup_membership_tokens(&[]);
+        contract.set_membership_price("group1".to_string(), Some(U128(1_000)));
+        let mut context = setup_context("user.near".parse().unwrap());
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(1_000)).build());
+        contract.buy_membership("group1".to_string());
+        // Receives then passes on a membership token
         let context = setup_context("auth-agent.devbot.near".parse().unwrap());
         testing_env!(context.build());
-        let trans_id = contract.record_transaction(
-            "group1".to_string(),
-            "user.near".parse().unwrap(),
-            "abc123".to_string(),
-            "QmTest".to_string(),
+        contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), None);
+        testing_env!(one_yocto_context("user.near").build());
+        contract.nft_transfer("alice.near".parse().unwrap(), "fan000".to_string(), None, None);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(contract.is_authorized("group1".to_string(), "alice.near".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_revoke_member_holding_token() {
+        let mut contract = setup_membership_tokens(&[]);
+        // Admitted directly, then given a token
+        contract.insert_group_member("group1", &"user.near".parse().unwrap());
+        contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), None);
+        contract.mint_membership_token("group1".to_string(), "fan001".to_string(), "user.near".parse().unwrap(), None);
+        // Revoking burns the member's tokens along with the direct membership
+        contract.revoke_group_member("group1".to_string(), "user.near".parse().unwrap());
+        assert!(!contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(contract.nft_token("fan000".to_string()).is_none());
+        assert!(contract.nft_token("fan001".to_string()).is_none());
+        assert_eq!(contract.nft_supply_for_owner("user.near".parse().unwrap()).0, 0);
+        assert_eq!(get_logs().last().unwrap(), "User user.near revoked from group group1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Membership tokens of group group1 are soulbound")]
+    fn test_membership_token_soulbound() {
+        let mut contract = setup_membership_tokens(&[("fan000", "user.near")]);
+        contract.set_group_soulbound("group1".to_string(), true);
+        assert!(contract.is_group_soulbound("group1".to_string()));
+        testing_env!(one_yocto_context("user.near").build());
+        contract.nft_transfer("alice.near".parse().unwrap(), "fan000".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_burn_membership_token() {
+        let mut contract = setup_membership_tokens(&[("fan000", "user.near")]);
+        testing_env!(setup_context("user.near".parse().unwrap()).build());
+        contract.burn_membership_token("fan000".to_string());
+        assert!(!contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(contract.nft_token("fan000".to_string()).is_none());
+        assert_eq!(contract.nft_total_supply().0, 0);
+        assert_eq!(contract.nft_supply_for_owner("user.near".parse().unwrap()).0, 0);
+        assert_eq!(get_logs().last().unwrap(), "Membership token fan000 of group group1 burned");
+    }
+
+    #[test]
+    fn test_nft_resolve_transfer_returns_membership() {
+        let mut contract = setup_membership_tokens(&[("fan000", "user.near")]);
+        testing_env!(one_yocto_context("user.near").build());
+        let _ = contract.nft_transfer_call("vault.near".parse().unwrap(), "fan000".to_string(), None, None, String::new());
+        assert!(contract.is_authorized("group1".to_string(), "vault.near".parse().unwrap()));
+
+        // The receiver asks for the token back
+        testing_env!(
+            setup_context("devbot.near".parse().unwrap()).build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
         );
-        // Store metadata
-        contract.store_file_metadata(trans_id.clone(), "file_size:1MB".to_string());
-        // Unauthorized caller
-        let context = setup_context("random.near".parse().unwrap());
-        testing_env!(context.build());
-        contract.get_file_metadata(trans_id);
+        let transferred = contract.nft_resolve_transfer("user.near".parse().unwrap(), "vault.near".parse().unwrap(), "fan000".to_string(), None);
+        assert!(!transferred);
+        assert!(contract.is_authorized("group1".to_string(), "user.near".parse().unwrap()));
+        assert!(!contract.is_authorized("group1".to_string(), "vault.near".parse().unwrap()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "to cover the storage")]
+    fn test_mint_membership_token_without_deposit() {
+        let mut contract = setup_membership_tokens(&[]);
+        testing_env!(one_yocto_context("auth-agent.devbot.near").build());
+        contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), None);
+    }
+
+    #[test]
+    fn test_mint_membership_token_refunds_excess() {
+        let mut contract = setup_membership_tokens(&[]);
+        contract.mint_membership_token("group1".to_string(), "fan000".to_string(), "user.near".parse().unwrap(), None);
+        // A single refund covers the token and the membership records
+        let refunds: Vec<NearToken> = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .filter(|receipt| receipt.receiver_id.as_str() == "auth-agent.devbot.near")
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                near_sdk::mock::MockAction::Transfer { deposit, .. } => Some(deposit),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(refunds.len(), 1);
+        assert!(refunds[0].as_yoctonear() > 0 && refunds[0] < NearToken::from_near(1));
+    }
+}
@@ -0,0 +1,204 @@
+// Sandbox tests running the DFS manager against the mock NFT contract in ../mock_nft
+use ed25519_dalek::{Signer, SigningKey};
+use near_sdk::{CurveType, PublicKey};
+use near_workspaces::{Account, Contract};
+use serde_json::json;
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+// Deploy and initialize both contracts and point the DFS manager at the mock NFT contract
+async fn setup() -> Result<(Contract, Contract, Account), Box<dyn std::error::Error>> {
+    let worker = near_workspaces::sandbox().await?;
+    let dfs = worker.dev_deploy(&near_workspaces::compile_project("./").await?).await?;
+    let nft = worker.dev_deploy(&near_workspaces::compile_project("../mock_nft").await?).await?;
+    dfs.call("new").transact().await?.into_result()?;
+    nft.call("new").transact().await?.into_result()?;
+    dfs.call("set_nft_contract").args_json(json!({ "nft_contract": nft.id() })).transact().await?.into_result()?;
+    dfs.call("register_group").args_json(json!({ "group_id": "group1" })).transact().await?.into_result()?;
+    let alice = worker.dev_create_account().await?;
+    Ok((dfs, nft, alice))
+}
+
+async fn mint(nft: &Contract, token_id: &str, receiver: &Account, group_id: &str) -> TestResult {
+    nft.call("nft_mint")
+        .args_json(json!({
+            "token_id": token_id,
+            "receiver_id": receiver.id(),
+            "metadata": {
+                "title": "1000fans Access Token",
+                "extra": json!({ "group_id": group_id }).to_string(),
+            },
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+async fn is_authorized(dfs: &Contract, account: &Account) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(dfs.view("is_authorized").args_json(json!({ "group_id": "group1", "user_id": account.id() })).await?.json()?)
+}
+
+#[tokio::test]
+async fn test_membership_lifecycle() -> TestResult {
+    let (dfs, nft, alice) = setup().await?;
+    mint(&nft, "fan000", &alice, "group1").await?;
+
+    // Add member through a real nft_tokens_for_owner call
+    let outcome = dfs
+        .call("add_group_member")
+        .args_json(json!({ "group_id": "group1", "user_id": alice.id() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:?}", outcome.failures());
+    assert!(is_authorized(&dfs, &alice).await?);
+
+    // Store key and read it back as the member, who pays for the access record
+    dfs.call("store_group_key")
+        .args_json(json!({ "group_id": "group1", "key": "symmetric_key_123" }))
+        .transact()
+        .await?
+        .into_result()?;
+    let key: String = alice
+        .call(dfs.id(), "request_group_key")
+        .args_json(json!({ "group_id": "group1" }))
+        .deposit(near_workspaces::types::NearToken::from_millinear(10))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(key, "symmetric_key_123");
+
+    // Record an attested upload
+    let signing_key = SigningKey::from_bytes(&[3u8; 32]);
+    let public_key = PublicKey::from_parts(CurveType::ED25519, signing_key.verifying_key().to_bytes().to_vec())?;
+    dfs.call("register_attestation_key")
+        .args_json(json!({ "public_key": public_key, "agent_id": dfs.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+    let claim = borsh::to_vec(&("group1", alice.id().as_str(), "abc123", "QmTest", 1_000u64))?;
+    let trans_id: String = dfs
+        .call("record_transaction")
+        .args_json(json!({
+            "group_id": "group1",
+            "user_id": alice.id(),
+            "file_hash": "abc123",
+            "ipfs_hash": "QmTest",
+            "size_bytes": "1000",
+            "attestation": {
+                "public_key": public_key,
+                "signature": hex::encode(signing_key.sign(&claim).to_bytes()),
+            },
+        }))
+        .transact()
+        .await?
+        .json()?;
+
+    // Transactions stay readable through plain RPC views
+    let tx: serde_json::Value = dfs.view("get_transaction").args_json(json!({ "trans_id": trans_id })).await?.json()?;
+    assert_eq!(tx["ipfs_hash"], "QmTest");
+
+    // Metadata is readable by the member
+    dfs.call("store_file_metadata")
+        .args_json(json!({ "trans_id": trans_id, "metadata": "{\"title\":\"Demo\"}" }))
+        .transact()
+        .await?
+        .into_result()?;
+    let metadata: Option<String> = alice
+        .call(dfs.id(), "get_file_metadata")
+        .args_json(json!({ "trans_id": trans_id }))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(metadata.as_deref(), Some("{\"title\":\"Demo\"}"));
+
+    // Revoked members lose access
+    dfs.call("revoke_group_member")
+        .args_json(json!({ "group_id": "group1", "user_id": alice.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(!is_authorized(&dfs, &alice).await?);
+    let outcome = alice
+        .call(dfs.id(), "request_group_key")
+        .args_json(json!({ "group_id": "group1" }))
+        .deposit(near_workspaces::types::NearToken::from_millinear(10))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_group_member_without_token() -> TestResult {
+    let (dfs, _nft, alice) = setup().await?;
+    let outcome = dfs
+        .call("add_group_member")
+        .args_json(json!({ "group_id": "group1", "user_id": alice.id() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+    assert!(format!("{:?}", outcome.failures()).contains("User does not own a 1000fans token"));
+    assert!(!is_authorized(&dfs, &alice).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_group_member_wrong_group_token() -> TestResult {
+    let (dfs, nft, alice) = setup().await?;
+    mint(&nft, "fan000", &alice, "group2").await?;
+    let outcome = dfs
+        .call("add_group_member")
+        .args_json(json!({ "group_id": "group1", "user_id": alice.id() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+    assert!(format!("{:?}", outcome.failures()).contains("Token group_id does not match group"));
+    assert!(!is_authorized(&dfs, &alice).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_group_member_failed_lookup() -> TestResult {
+    let (dfs, nft, alice) = setup().await?;
+    mint(&nft, "fan000", &alice, "group1").await?;
+    nft.call("set_fail_lookups").args_json(json!({ "fail": true })).transact().await?.into_result()?;
+    let outcome = dfs
+        .call("add_group_member")
+        .args_json(json!({ "group_id": "group1", "user_id": alice.id() }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+    assert!(format!("{:?}", outcome.failures()).contains("Failed to check token ownership"));
+    assert!(!is_authorized(&dfs, &alice).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_native_membership_token() -> TestResult {
+    let (dfs, _nft, alice) = setup().await?;
+    let bob = dfs.as_account().create_subaccount("bob").transact().await?.into_result()?;
+    dfs.call("mint_membership_token")
+        .args_json(json!({ "group_id": "group1", "token_id": "member000", "receiver_id": alice.id() }))
+        .deposit(near_workspaces::types::NearToken::from_millinear(100))
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(is_authorized(&dfs, &alice).await?);
+
+    // Membership moves with the token
+    alice
+        .call(dfs.id(), "nft_transfer")
+        .args_json(json!({ "receiver_id": bob.id(), "token_id": "member000" }))
+        .deposit(near_workspaces::types::NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+    assert!(!is_authorized(&dfs, &alice).await?);
+    assert!(is_authorized(&dfs, &bob).await?);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// A minimal NEP-171 contract standing in for 1000fans.testnet in the DFS manager sandbox tests
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{near_bindgen, env, log, PanicOnDefault, AccountId};
+use near_sdk::store::IterableMap;
+use near_sdk::json_types::U128;
+use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+
+#[near_bindgen]
+#[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct Contract {
+    tokens: IterableMap<TokenId, (AccountId, TokenMetadata)>,
+    fail_lookups: bool, // Makes nft_tokens_for_owner panic, to exercise failed callbacks
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new() -> Self {
+        Self {
+            tokens: IterableMap::new(b"t"),
+            fail_lookups: false,
+        }
+    }
+
+    // Mint a token, the DFS manager reads group_id from metadata.extra
+    pub fn nft_mint(&mut self, token_id: TokenId, receiver_id: AccountId, metadata: TokenMetadata) -> Token {
+        assert!(!self.tokens.contains_key(&token_id), "Token already exists");
+        self.tokens.insert(token_id.clone(), (receiver_id.clone(), metadata));
+        log!("Token {} minted for {}", token_id, receiver_id);
+        self.nft_token(token_id).unwrap()
+    }
+
+    // Move a token without approvals or payouts
+    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: TokenId) {
+        let (owner_id, _) = self.tokens.get_mut(&token_id).expect("Token not found");
+        assert!(*owner_id == env::predecessor_account_id(), "Only the token owner can transfer");
+        *owner_id = receiver_id;
+    }
+
+    pub fn set_fail_lookups(&mut self, fail: bool) {
+        self.fail_lookups = fail;
+    }
+
+    pub fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.tokens.get(&token_id).map(|(owner_id, metadata)| Token {
+            token_id,
+            owner_id: owner_id.clone(),
+            metadata: Some(metadata.clone()),
+            approved_account_ids: None,
+        })
+    }
+
+    pub fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        assert!(!self.fail_lookups, "Token lookups disabled");
+        self.tokens
+            .iter()
+            .filter(|(_, (owner_id, _))| *owner_id == account_id)
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.unwrap_or(50) as usize)
+            .map(|(token_id, _)| self.nft_token(token_id.clone()).unwrap())
+            .collect()
+    }
+}